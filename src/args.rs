@@ -0,0 +1,209 @@
+//! Parsing of the command line.
+
+use std::path::Path;
+
+pub const USAGE: &str = "\
+Usage: edit-rust [OPTIONS] [+LINE] [FILE[:LINE[:COL]]]...
+       edit-rust --filter [OPTIONS]
+       edit-rust (--batch SCRIPT | -c COMMAND)... [OPTIONS] [FILE[:LINE[:COL]]]...
+
+Opens each FILE in its own buffer, creating it when it's first saved if it doesn't exist yet.
+A FILE of - reads stdin into a scratch buffer, as does piping into the editor without naming any files.
+Lines and columns start at 1. +LINE moves the cursor to LINE in the file after it.
+
+Options:
+  -R, --readonly    Open every file read-only
+      --filter      Edit stdin, and write the result to stdout once it's saved and the editor is quit.
+                    Quitting without saving (or with :q!) exits with an error instead
+      --batch PATH  Run the commands in PATH, one per line, without opening the terminal. Blank lines and
+                    lines starting with # are skipped. Stdin is read when it's piped and no files are given
+  -c COMMAND        Run COMMAND as part of the batch script (the two can be mixed, and run in the order given)
+      --theme NAME  Use the theme NAME from $XDG_CONFIG_HOME/edit-rust/themes (~/.config/edit-rust/themes
+                    by default), or from a file if NAME is a path. Overrides the theme in the config file
+      --config PATH Load settings from PATH
+  -h, --help        Print this message and exit
+  -V, --version     Print the version and exit";
+
+///A file to open, and where to put the cursor in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileArg {
+	pub path: String,
+	///The zero-indexed line and column to start at.
+	pub position: Option<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Args {
+	pub files: Vec<FileArg>,
+	pub readonly: bool,
+	///Edit stdin and pass the result on to stdout, instead of editing files.
+	pub filter: bool,
+	///Commands to run in batch mode. Batch mode is only used if there are any.
+	pub script: Vec<ScriptPart>,
+	pub theme: Option<String>,
+	pub config: Option<String>,
+}
+
+///Part of the script run in batch mode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptPart {
+	///A file of commands.
+	File(String),
+	Command(String),
+}
+
+///What the command line asks for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+	Edit(Args),
+	Help,
+	Version,
+}
+
+///Parses the arguments after the program name. `exists` is used to tell whether an argument like `a:1` is a position
+///in `a`, or a file which is really called `a:1`.
+pub fn parse(args: impl IntoIterator<Item = String>, exists: impl Fn(&str) -> bool) -> Result<Action, String> {
+	let mut parsed = Args::default();
+	let mut line = None;
+	let mut options_ended = false;
+
+	let mut args = args.into_iter();
+	while let Some(arg) = args.next() {
+		if !options_ended && arg.starts_with('-') && arg != "-" {
+			let (name, inline_value) = match arg.split_once('=') {
+				Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+				_ => (arg.as_str(), None),
+			};
+			let mut value = || inline_value.clone().or_else(|| args.next()).ok_or_else(|| format!("{name} needs a value"));
+
+			match name {
+				"--" => options_ended = true,
+				"-R" | "--readonly" => parsed.readonly = true,
+				"--filter" => parsed.filter = true,
+				"--batch" => parsed.script.push(ScriptPart::File(value()?)),
+				"-c" => parsed.script.push(ScriptPart::Command(value()?)),
+				"--theme" => parsed.theme = Some(value()?),
+				"--config" => parsed.config = Some(value()?),
+				"-h" | "--help" => return Ok(Action::Help),
+				"-V" | "--version" => return Ok(Action::Version),
+				_ => return Err(format!("Unknown option: {name}")),
+			}
+		} else if let (false, Some(number)) = (options_ended, arg.strip_prefix('+')) {
+			line = Some(parse_number(number)?);
+		} else {
+			let mut file = split_position(&arg, &exists);
+			if let Some(line) = line.take() {
+				file.position = Some((line, 0));
+			}
+			parsed.files.push(file);
+		}
+	}
+
+	if line.is_some() {
+		return Err("+LINE must come before the file it applies to".to_string());
+	}
+	if parsed.filter && !parsed.files.is_empty() {
+		return Err("--filter edits stdin, so it can't be given files".to_string());
+	}
+	if parsed.filter && !parsed.script.is_empty() {
+		return Err("--filter can't be used in batch mode".to_string());
+	}
+
+	Ok(Action::Edit(parsed))
+}
+
+///Parses a one-indexed line or column number, returning it zero-indexed.
+fn parse_number(number: &str) -> Result<usize, String> {
+	match number.parse::<usize>() {
+		Ok(n) => Ok(n.saturating_sub(1)),
+		Err(_) => Err(format!("Expected a line number, found \"{number}\"")),
+	}
+}
+
+///Splits a `path:line:col` or `path:line` argument. Files which exist under the full name are always taken as they are.
+fn split_position(arg: &str, exists: impl Fn(&str) -> bool) -> FileArg {
+	let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+	//Numbers too big to fit are past the end of any file, where the cursor gets clamped to anyway.
+	let index = |s: &str| s.parse::<usize>().map_or(usize::MAX, |n| n.saturating_sub(1));
+
+	if !exists(arg) {
+		if let Some((rest, last)) = arg.rsplit_once(':').filter(|(rest, last)| !rest.is_empty() && is_number(last)) {
+			return match rest.rsplit_once(':') {
+				Some((path, line)) if !path.is_empty() && is_number(line) => {
+					FileArg { path: path.to_string(), position: Some((index(line), index(last))) }
+				},
+				_ => FileArg { path: rest.to_string(), position: Some((index(last), 0)) },
+			};
+		}
+	}
+
+	FileArg { path: arg.to_string(), position: None }
+}
+
+///Returns true if something exists at `path`, for use with [parse].
+pub fn path_exists(path: &str) -> bool {
+	Path::new(path).exists()
+}
+
+#[cfg(test)]
+mod test {
+	use super::{parse, Action, Args, FileArg, ScriptPart};
+
+	fn args(list: &[&str]) -> Result<Action, String> {
+		parse(list.iter().map(|a| a.to_string()), |p| p == "exists:1")
+	}
+	fn file(path: &str, position: Option<(usize, usize)>) -> FileArg {
+		FileArg { path: path.to_string(), position }
+	}
+
+	#[test]
+	fn files_and_positions() {
+		assert_eq!(args(&["a.txt", "+3", "b.txt", "c.txt:10", "d.txt:2:5", "exists:1"]), Ok(Action::Edit(Args {
+			files: vec![
+				file("a.txt", None),
+				file("b.txt", Some((2, 0))),
+				file("c.txt", Some((9, 0))),
+				file("d.txt", Some((1, 4))),
+				file("exists:1", None),
+			],
+			..Args::default()
+		})));
+		assert_eq!(args(&["a:", ":3", "a:b"]), Ok(Action::Edit(Args {
+			files: vec![file("a:", None), file(":3", None), file("a:b", None)],
+			..Args::default()
+		})));
+		assert!(args(&["+x", "a.txt"]).is_err());
+		assert!(args(&["a.txt", "+3"]).is_err());
+	}
+
+	#[test]
+	fn options() {
+		assert_eq!(args(&["-R", "--theme", "t.toml", "--config=c.toml", "--", "-R", "+1"]), Ok(Action::Edit(Args {
+			files: vec![file("-R", None), file("+1", None)],
+			readonly: true,
+			filter: false,
+			script: vec![],
+			theme: Some("t.toml".to_string()),
+			config: Some("c.toml".to_string()),
+		})));
+		assert_eq!(args(&["a.txt", "--help"]), Ok(Action::Help));
+		assert_eq!(args(&["-V"]), Ok(Action::Version));
+		assert!(args(&["--theme"]).is_err());
+		assert!(args(&["--frobnicate"]).is_err());
+		assert!(args(&["--filter", "a.txt"]).is_err());
+		assert!(args(&["--filter", "-c", "p"]).is_err());
+	}
+
+	#[test]
+	fn batch_script() {
+		assert_eq!(args(&["-c", "s/a/b/", "--batch", "mod.txt", "a.txt", "-c", "w"]), Ok(Action::Edit(Args {
+			files: vec![file("a.txt", None)],
+			script: vec![
+				ScriptPart::Command("s/a/b/".to_string()),
+				ScriptPart::File("mod.txt".to_string()),
+				ScriptPart::Command("w".to_string()),
+			],
+			..Args::default()
+		})));
+	}
+}
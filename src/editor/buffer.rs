@@ -1,73 +1,1066 @@
-use super::{rope::{Rope, RopeIterator}, Editor, Mode, Source};
+use std::ops::Range;
+
+use regex::bytes::Regex;
+
+use super::{config::Config, grapheme, history::{self, Edit, Group, History, Reader, Transaction}, rope::{Rope, RopeIterator}, File, Mode, Source};
 use crate::terminil::prelude::*;
 
-#[derive(Debug, Clone, Copy)]
-struct SelectionPoint {
-	line: u32,
-	col: u32,
-	target_col: u32,
+///A position within a buffer. Lines and columns are zero-indexed, and columns count characters rather than bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct SelectionPoint {
+	line: usize,
+	col: usize,
+	///The column this point tries to return to when moving vertically, so that passing through a short line doesn't
+	///lose the original column.
+	target_col: usize,
+} impl SelectionPoint {
+	fn from_offset(rope: &Rope, offset: usize) -> Self {
+		let line = rope.byte_to_line(offset);
+		let col = rope.byte_to_char(offset) - rope.byte_to_char(rope.line_to_byte(line));
+
+		SelectionPoint { line, col, target_col: col }
+	}
+
+	fn to_offset(self, rope: &Rope) -> usize {
+		let line_start = rope.line_to_byte(self.line);
+		rope.char_to_byte(rope.byte_to_char(line_start) + self.col)
+	}
+
+	///Moves horizontally by `offset_x` grapheme clusters, wrapping across line ends, and then vertically by `offset_y` lines.
+	fn move_by(&mut self, rope: &Rope, offset_x: i32, offset_y: i32) {
+		if offset_x != 0 {
+			let mut offset = self.to_offset(rope);
+			for _ in 0..offset_x.unsigned_abs() {
+				//Grapheme boundaries include the boundaries on either side of a line break, so this also wraps lines.
+				offset = if offset_x > 0 {
+					rope.next_grapheme_boundary(offset)
+				} else {
+					rope.prev_grapheme_boundary(offset)
+				};
+			}
+			*self = SelectionPoint::from_offset(rope, offset);
+		}
+
+		if offset_y != 0 {
+			let line = self.line.saturating_add_signed(offset_y as isize).min(rope.line_count() - 1);
+			let line_start = rope.line_to_byte(line);
+			let line_end = line_end(rope, line);
+			let first_char = rope.byte_to_char(line_start);
+
+			let col = self.target_col.min(rope.byte_to_char(line_end) - first_char);
+			let mut offset = rope.char_to_byte(first_char + col);
+			if offset < line_end {
+				//Don't land in the middle of a grapheme cluster.
+				offset = rope.prev_grapheme_boundary(rope.char_to_byte(first_char + col + 1));
+			}
+
+			self.line = line;
+			self.col = rope.byte_to_char(offset) - first_char;
+		}
+	}
+
+	fn encode(&self, out: &mut Vec<u8>) {
+		for value in [self.line, self.col, self.target_col] {
+			history::write_u64(out, value as u64);
+		}
+	}
+	fn decode(reader: &mut Reader) -> Option<Self> {
+		Some(SelectionPoint { line: reader.usize()?, col: reader.usize()?, target_col: reader.usize()? })
+	}
+}
+
+///Returns the offset just past the last character of `line`, before any line terminator.
+fn line_end(rope: &Rope, line: usize) -> usize {
+	let range = rope.line_range(line);
+	let mut end = range.end;
+	if end > range.start && rope.byte_at(end - 1) == b'\n' {
+		end -= 1;
+		if end > range.start && rope.byte_at(end - 1) == b'\r' {
+			end -= 1;
+		}
+	}
+
+	end
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Selection {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Selection {
 	Point(SelectionPoint),
-	Range { start_line: u32, start_col: u32, end: SelectionPoint },
+	///A selection between a fixed anchor (`start_line`, `start_col`) and a moving `end`.
+	Range { start_line: usize, start_col: usize, end: SelectionPoint },
 } impl Selection {
-	pub fn move_by(&mut self, offsetX: i32, offsetY: i32) {
+	///Moves the cursor of the selection. Ranges keep their anchor in place and only move their end.
+	pub fn move_by(&mut self, rope: &Rope, offset_x: i32, offset_y: i32) {
+		match self {
+			Selection::Point(p) => p.move_by(rope, offset_x, offset_y),
+			Selection::Range { end, .. } => end.move_by(rope, offset_x, offset_y),
+		}
+	}
+
+	///Returns the point which moves when the selection does.
+	fn cursor(&self) -> SelectionPoint {
+		match self {
+			Selection::Point(p) => *p,
+			Selection::Range { end, .. } => *end,
+		}
+	}
+
+	///Returns the bytes covered by the selection. Points cover an empty range at the cursor.
+	fn byte_range(&self, rope: &Rope) -> Range<usize> {
 		match self {
 			Selection::Point(p) => {
-				// ? In order to move, we need to know the length of the lines around the selection.
-				// ? That way the cursor can wrap and/or set its target_col.
-				// ! It seems as if my modularity is failing here.
-			}
+				let offset = p.to_offset(rope);
+				offset..offset
+			},
 			Selection::Range { start_line, start_col, end } => {
+				let anchor = SelectionPoint { line: *start_line, col: *start_col, target_col: *start_col }.to_offset(rope);
+				let end = end.to_offset(rope);
+				anchor.min(end)..anchor.max(end)
+			},
+		}
+	}
 
-			}
+	///Appends the selection to a stored undo history.
+	pub fn encode(&self, out: &mut Vec<u8>) {
+		match self {
+			Selection::Point(p) => {
+				history::write_u64(out, 0);
+				p.encode(out);
+			},
+			Selection::Range { start_line, start_col, end } => {
+				history::write_u64(out, 1);
+				history::write_u64(out, *start_line as u64);
+				history::write_u64(out, *start_col as u64);
+				end.encode(out);
+			},
+		}
+	}
+	pub fn decode(reader: &mut Reader) -> Option<Self> {
+		match reader.u64()? {
+			0 => Some(Selection::Point(SelectionPoint::decode(reader)?)),
+			1 => Some(Selection::Range {
+				start_line: reader.usize()?,
+				start_col: reader.usize()?,
+				end: SelectionPoint::decode(reader)?,
+			}),
+			_ => None,
 		}
 	}
 }
 
+///The part of a buffer which is shown on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Viewport {
+	///The first line shown.
+	pub top_line: usize,
+	///The first display column shown. Display columns count screen cells, so wide characters take up two.
+	pub left_col: usize,
+}
+
+///Returns the new start of a view of `size` cells along one axis, moved as little as possible so that `pos` is at
+///least `margin` cells from either edge. The margin shrinks on views too small to fit it on both sides.
+fn follow(start: usize, pos: usize, size: usize, margin: usize) -> usize {
+	let margin = margin.min(size.saturating_sub(1) / 2);
+	if pos < start + margin {
+		pos.saturating_sub(margin)
+	} else if pos + margin >= start + size {
+		pos + margin + 1 - size
+	} else {
+		start
+	}
+}
+
 pub struct Buffer {
 	selections: Vec<Selection>,
+	viewport: Viewport,
 	source: Source,
 	rope: Rope,
+	history: History,
+	///The history revision which was last written to disk, used to tell if there are unsaved changes.
+	saved_revision: usize,
+	///The content hash of the file as it was last read or written, which a stored history must match to be reused.
+	saved_hash: u64,
+	///Set once a [Source::Filter] buffer has been saved. Its contents are only passed on if the user did so.
+	filter_saved: bool,
 } impl Buffer {
 	pub fn new(source: Source) -> Result<Self, std::io::Error> {
 		let mut r;
 		match &source {
-			Source::File(f) => r = f.read_to_rope()?,
-			Source::String(s) => {
+			Source::File(f) => r = match f.read_to_rope() {
+				//The file gets created when the buffer is first saved.
+				Err(e) if e.kind() == std::io::ErrorKind::NotFound => Rope::new(),
+				result => result?,
+			},
+			Source::Scratch(data) | Source::Filter(data) => {
 				r = Rope::new();
-				r.insert_bytes(s.as_bytes(), 0);
-			}
+				r.insert_bytes(data, 0);
+			},
 		}
-		let mut selections =  Vec::with_capacity(1);
-		selections.push(Selection::Point(SelectionPoint { line: 1, col: 1, target_col: 1 }));
+		let selections = vec![Selection::Point(SelectionPoint { line: 0, col: 0, target_col: 0 })];
+		let saved_hash = history::content_hash(&r);
+
+		let history = match (&source, history::undo_dir()) {
+			(Source::File(f), Some(dir)) => History::load(&dir, &undo_key(f), saved_hash),
+			_ => None,
+		}.unwrap_or_else(History::new);
 
 		Ok(Buffer {
 			selections,
+			viewport: Viewport::default(),
 			source,
 			rope: r,
+			saved_revision: history.current(),
+			history,
+			saved_hash,
+			filter_saved: false,
 		})
 	}
-	fn move_cursor(&mut self, offsetX: i32, offsetY: i32) {
+	pub fn rope(&self) -> &Rope {
+		&self.rope
+	}
+	///Returns a name for the buffer suitable for showing to the user.
+	pub fn name(&self) -> &str {
+		match &self.source {
+			Source::File(f) => f.path(),
+			Source::Scratch(_) => "[scratch]",
+			Source::Filter(_) => "[filter]",
+		}
+	}
+	pub fn source(&self) -> &Source {
+		&self.source
+	}
+	///Returns the file the buffer was opened from, if it has one.
+	pub fn file(&self) -> Option<&File> {
+		match &self.source {
+			Source::File(f) => Some(f),
+			Source::Scratch(_) | Source::Filter(_) => None,
+		}
+	}
+	///Returns the [content hash](history::content_hash) of the buffer's file as it was last read or written.
+	pub fn saved_hash(&self) -> u64 {
+		self.saved_hash
+	}
+	///Returns true if the buffer has changed since it was last saved (or opened).
+	pub fn is_modified(&self) -> bool {
+		self.history.current() != self.saved_revision
+	}
+	pub fn filter_saved(&self) -> bool {
+		self.filter_saved
+	}
+	///Writes the buffer back to the file it was opened from.
+	pub fn save(&mut self) -> Result<(), std::io::Error> {
+		match &self.source {
+			Source::File(f) => f.write_rope(&self.rope)?,
+			Source::Filter(_) => self.filter_saved = true,
+			Source::Scratch(_) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "buffer has no file name")),
+		}
+		self.mark_saved();
+
+		Ok(())
+	}
+	///Writes the buffer to `path`, which becomes the buffer's file if the write succeeds. Filter buffers can't be
+	///saved elsewhere, as they'd stop being passed on to stdout.
+	pub fn save_as(&mut self, path: &str) -> Result<(), std::io::Error> {
+		if let Source::Filter(_) = self.source {
+			return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "filter mode only writes to stdout"));
+		}
+		let file = File::open(path, false);
+		file.write_rope(&self.rope)?;
+		self.source = Source::File(file);
+		self.mark_saved();
+
+		Ok(())
+	}
+	fn mark_saved(&mut self) {
+		self.saved_revision = self.history.current();
+		self.saved_hash = history::content_hash(&self.rope);
+		//Further typing must start a new revision, or it would be merged into the one on disk.
+		self.history.close_group();
+		let _ = self.store_history();
+	}
+	///Stores the undo history in the cache directory so it can be restored the next time the file is opened.
+	///
+	///Unsaved changes are kept as a redo branch from the revision on disk.
+	pub fn store_history(&self) -> Result<(), std::io::Error> {
+		match (&self.source, history::undo_dir()) {
+			//A history without any changes has nothing worth keeping.
+			_ if self.history.newest() == 0 => Ok(()),
+			(Source::File(f), Some(dir)) => self.history.store(&dir, &undo_key(f), self.saved_revision, self.saved_hash),
+			_ => Ok(()),
+		}
+	}
+	///Returns the `(line, col)` of the primary cursor.
+	pub fn cursor(&self) -> (usize, usize) {
+		let cursor = self.selections[0].cursor();
+		(cursor.line, cursor.col)
+	}
+	///Returns the byte offset of the primary cursor.
+	pub fn cursor_offset(&self) -> usize {
+		self.selections[0].cursor().to_offset(&self.rope)
+	}
+	///Returns the display column of the primary cursor within its line.
+	pub fn cursor_display_col(&self) -> usize {
+		let line_start = self.rope.line_to_byte(self.cursor().0);
+		grapheme::clusters(&self.rope.bytes_in(line_start..self.cursor_offset())).fold(0, grapheme::next_col)
+	}
+	pub fn viewport(&self) -> Viewport {
+		self.viewport
+	}
+	///Scrolls a `width` by `height` viewport so the primary cursor stays within the configured margins.
+	pub fn scroll_to_cursor(&mut self, width: usize, height: usize, config: &Config) {
+		let (line, _) = self.cursor();
+		self.viewport.top_line = follow(self.viewport.top_line, line, height, config.scroll_margin);
+		self.viewport.left_col = follow(self.viewport.left_col, self.cursor_display_col(), width, config.side_scroll_margin);
+	}
+	///Scrolls the viewport by `lines`, moving the cursor along if it would leave the margins of a viewport `height`
+	///lines tall.
+	pub fn scroll_by(&mut self, lines: isize, height: usize, config: &Config) {
+		let last_line = self.rope.line_count() - 1;
+		let top = self.viewport.top_line.saturating_add_signed(lines).min(last_line);
+		self.viewport.top_line = top;
+
+		let margin = config.scroll_margin.min(height.saturating_sub(1) / 2);
+		let highest = if top == 0 { 0 } else { top + margin };
+		let lowest = (top + height).saturating_sub(margin + 1).max(highest);
+		let (line, _) = self.cursor();
+		let target = line.clamp(highest, lowest).min(last_line);
+		if target != line {
+			self.move_cursor(0, target as i32 - line as i32);
+		}
+	}
+	///Returns the position shown in cell `(x, y)` of the viewport, clamped to the text.
+	fn point_at(&self, x: usize, y: usize) -> SelectionPoint {
+		let line = (self.viewport.top_line + y).min(self.rope.line_count() - 1);
+		let target = self.viewport.left_col + x;
+		let bytes = self.rope.bytes_in(self.rope.line_to_byte(line)..line_end(&self.rope, line));
+
+		let mut display_col = 0;
+		let mut col = 0;
+		for cluster in grapheme::clusters(&bytes) {
+			display_col = grapheme::next_col(display_col, cluster);
+			if display_col > target {
+				break;
+			}
+			col += grapheme::count_chars(cluster);
+		}
+
+		SelectionPoint { line, col, target_col: col }
+	}
+	///Replaces every selection with a single cursor at `line` and `col` (counted in characters), clamped to the buffer's
+	///contents.
+	pub fn jump_to(&mut self, line: usize, col: usize) {
+		let line = line.min(self.rope.line_count() - 1);
+		let bytes = self.rope.bytes_in(self.rope.line_to_byte(line)..line_end(&self.rope, line));
+
+		let mut reached = 0;
+		for cluster in grapheme::clusters(&bytes) {
+			let next = reached + grapheme::count_chars(cluster);
+			if next > col {
+				break;
+			}
+			reached = next;
+		}
+
+		self.history.close_group();
+		self.selections = vec![Selection::Point(SelectionPoint { line, col: reached, target_col: reached })];
+	}
+	///Replaces every selection with a single cursor at cell `(x, y)` of the viewport.
+	pub fn click(&mut self, x: usize, y: usize) {
+		self.history.close_group();
+		self.selections = vec![Selection::Point(self.point_at(x, y))];
+	}
+	///Extends the primary selection to cell `(x, y)` of the viewport, keeping its anchor in place. Other selections
+	///are dropped.
+	pub fn drag_to(&mut self, x: usize, y: usize) {
+		let end = self.point_at(x, y);
+		let (start_line, start_col) = match self.selections[0] {
+			Selection::Point(p) => (p.line, p.col),
+			Selection::Range { start_line, start_col, .. } => (start_line, start_col),
+		};
+
+		self.selections = vec![if (start_line, start_col) == (end.line, end.col) {
+			Selection::Point(end)
+		} else {
+			Selection::Range { start_line, start_col, end }
+		}];
+	}
+	///Returns the bytes covered by each selection, for highlighting them.
+	pub fn selection_ranges(&self) -> Vec<Range<usize>> {
+		self.selections.iter().map(|s| s.byte_range(&self.rope)).collect()
+	}
+	fn move_cursor(&mut self, offset_x: i32, offset_y: i32) {
+		self.history.close_group();
 		for sel in &mut self.selections {
-			sel.move_by(offsetX, offsetY);
+			sel.move_by(&self.rope, offset_x, offset_y);
+		}
+	}
+
+	///Replaces the text around every selection with `text`, leaving a cursor just after each insertion.
+	///
+	///`extend` receives the bytes covered by each selection and returns the range which should actually be replaced,
+	///which lets deletions reach past empty selections. Overlapping ranges are clipped so no byte is edited twice.
+	///
+	///The whole edit is recorded in the history as one transaction, which is merged into the previous one if both
+	///belong to the same `group`.
+	fn edit_selections(&mut self, text: &[u8], group: Option<Group>, extend: impl Fn(&Rope, Range<usize>) -> Range<usize>) {
+		let mut ranges: Vec<Range<usize>> = self.selections.iter()
+			.map(|s| extend(&self.rope, s.byte_range(&self.rope)))
+			.collect();
+		ranges.sort_by_key(|r| r.start);
+		for i in 1..ranges.len() {
+			let previous_end = ranges[i - 1].end;
+			ranges[i].start = ranges[i].start.max(previous_end);
+			ranges[i].end = ranges[i].end.max(ranges[i].start);
+		}
+		//Cursors which ended up in the same place, or were swallowed by another selection, only get edited once.
+		ranges.dedup_by(|range, previous| range.start == range.end && range.start == previous.end);
+
+		if ranges.iter().all(|r| r.is_empty()) && text.is_empty() {
+			return;
+		}
+
+		//Editing back to front keeps the offsets of the remaining ranges valid.
+		let mut edits = Vec::with_capacity(ranges.len());
+		for range in ranges.iter().rev() {
+			edits.push(Edit {
+				start: range.start,
+				deleted: self.rope.bytes_in(range.clone()),
+				inserted: text.to_vec(),
+			});
+			self.rope.replace_range(range.clone(), text);
+		}
+		let selections_before = self.selections.clone();
+
+		let mut shift = 0isize;
+		let mut cursors = Vec::with_capacity(ranges.len());
+		for range in &ranges {
+			let cursor = range.start.saturating_add_signed(shift) + text.len();
+			shift += text.len() as isize - range.len() as isize;
+			if cursors.last() != Some(&cursor) {
+				cursors.push(cursor);
+			}
+		}
+
+		self.selections = cursors.into_iter()
+			.map(|offset| Selection::Point(SelectionPoint::from_offset(&self.rope, offset)))
+			.collect();
+
+		self.history.record(Transaction {
+			edits,
+			selections_before,
+			selections_after: self.selections.clone(),
+		}, group);
+	}
+
+	///Replaces the text around every selection with `text`.
+	pub fn insert(&mut self, text: &[u8]) {
+		self.edit_selections(text, None, |_, range| range);
+	}
+
+	///Applies `edits` as one transaction and then moves the cursor to `(line, col)`. The edits must be ordered back to
+	///front, so that none of them moves the text another refers to.
+	fn apply_edits(&mut self, edits: Vec<Edit>, (line, col): (usize, usize)) {
+		let selections_before = self.selections.clone();
+		for edit in &edits {
+			self.rope.replace_range(edit.start..edit.start + edit.deleted.len(), &edit.inserted);
+		}
+		self.jump_to(line, col);
+
+		self.history.record(Transaction {
+			edits,
+			selections_before,
+			selections_after: self.selections.clone(),
+		}, None);
+	}
+
+	///Moves the cursor to the start of the next match of `regex` after it, wrapping around the end of the buffer.
+	///Returns false if nothing matches.
+	pub fn search(&mut self, regex: &Regex) -> bool {
+		let text = self.rope.bytes_in(0..self.rope.len());
+		let cursor = self.cursor_offset();
+		let found = regex.find_iter(&text).map(|m| m.start()).find(|start| *start > cursor)
+			.or_else(|| regex.find(&text).map(|m| m.start()));
+
+		match found {
+			Some(offset) => {
+				self.history.close_group();
+				self.selections = vec![Selection::Point(SelectionPoint::from_offset(&self.rope, offset))];
+				true
+			},
+			None => false,
+		}
+	}
+
+	///Replaces matches of `regex` on every line with `replacement`, which can refer to capture groups as `$1` or
+	///`${name}`. Only the first match on each line is replaced unless `global` is set. Returns how many matches were
+	///replaced, which are undone together.
+	pub fn substitute(&mut self, regex: &Regex, replacement: &[u8], global: bool) -> usize {
+		let mut edits = Vec::new();
+		for line in (0..self.rope.line_count()).rev() {
+			let start = self.rope.line_to_byte(line);
+			let bytes = self.rope.bytes_in(start..line_end(&self.rope, line));
+
+			let matches = regex.captures_iter(&bytes).take(if global { usize::MAX } else { 1 });
+			let mut line_edits: Vec<Edit> = matches.map(|captures| {
+				let whole = captures.get(0).expect("the whole match to always be captured");
+				let mut inserted = Vec::new();
+				captures.expand(replacement, &mut inserted);
+				Edit { start: start + whole.start(), deleted: whole.as_bytes().to_vec(), inserted }
+			}).collect();
+			line_edits.reverse();
+			edits.append(&mut line_edits);
+		}
+
+		let count = edits.len();
+		if count > 0 {
+			self.apply_edits(edits, self.cursor());
+		}
+
+		count
+	}
+
+	///Deletes `count` lines, starting from the cursor's, and leaves the cursor at the start of the line after them.
+	pub fn delete_lines(&mut self, count: usize) {
+		let line_count = self.rope.line_count();
+		let (line, _) = self.cursor();
+		let end_line = line.saturating_add(count).min(line_count);
+
+		let mut range = self.rope.line_to_byte(line)..if end_line < line_count {
+			self.rope.line_to_byte(end_line)
+		} else {
+			self.rope.len()
+		};
+		//With no line after them to take their place, the line break before the deleted lines goes instead.
+		if end_line == line_count && line > 0 {
+			range.start = line_end(&self.rope, line - 1);
+		}
+
+		if !range.is_empty() {
+			let edit = Edit { start: range.start, deleted: self.rope.bytes_in(range), inserted: Vec::new() };
+			self.apply_edits(vec![edit], (line, 0));
+		}
+	}
+
+	///Inserts a typed character. Consecutive characters are undone together, up to the end of a word.
+	fn type_char(&mut self, c: char) {
+		self.edit_selections(c.encode_utf8(&mut [0; 4]).as_bytes(), Some(Group::Typing), |_, range| range);
+		if c.is_whitespace() {
+			self.history.close_group();
 		}
 	}
+
+	///Deletes each selection, or the grapheme cluster before each cursor.
+	fn backspace(&mut self) {
+		self.edit_selections(b"", Some(Group::Deleting), |rope, range| if range.is_empty() {
+			rope.prev_grapheme_boundary(range.start)..range.end
+		} else {
+			range
+		});
+	}
+
+	///Deletes each selection, or the grapheme cluster after each cursor.
+	fn delete(&mut self) {
+		self.edit_selections(b"", Some(Group::Deleting), |rope, range| if range.is_empty() {
+			range.start..rope.next_grapheme_boundary(range.end)
+		} else {
+			range
+		});
+	}
+
+	///Reverts the most recent transaction. Returns false if there was nothing to undo.
+	pub fn undo(&mut self) -> bool {
+		match self.history.undo() {
+			Some(transaction) => {
+				transaction.revert(&mut self.rope);
+				self.selections = transaction.selections_before.clone();
+				true
+			},
+			None => false,
+		}
+	}
+
+	///Re-applies the most recently undone transaction. Returns false if there was nothing to redo.
+	pub fn redo(&mut self) -> bool {
+		match self.history.redo() {
+			Some(transaction) => {
+				transaction.apply(&mut self.rope);
+				self.selections = transaction.selections_after.clone();
+				true
+			},
+			None => false,
+		}
+	}
+
+	///Moves chronologically through the history by `steps` revisions, crossing between branches of the undo tree.
+	///Returns the number of revisions actually moved.
+	pub fn time_travel(&mut self, steps: isize) -> usize {
+		let current = self.history.current();
+		let target = current.saturating_add_signed(steps).min(self.history.newest());
+
+		let (undo, redo) = self.history.goto(target);
+		for transaction in undo {
+			transaction.revert(&mut self.rope);
+			self.selections = transaction.selections_before.clone();
+		}
+		for transaction in redo {
+			transaction.apply(&mut self.rope);
+			self.selections = transaction.selections_after.clone();
+		}
+
+		target.abs_diff(current)
+	}
+
 	pub fn trigger_event(&mut self, event: Event, mode: &Mode) {
 		match (mode, event) {
-			(_, Event::SpecialKey(SpecialKey::Up))    => self.move_cursor( 0, -1),
-			(_, Event::SpecialKey(SpecialKey::Down))  => self.move_cursor( 0,  1),
-			(_, Event::SpecialKey(SpecialKey::Left))  => self.move_cursor(-1,  0),
-			(_, Event::SpecialKey(SpecialKey::Right)) => self.move_cursor( 1,  0),
+			(mode, Event::Key(KeyEvent { key, modifiers: Modifiers::NONE, kind: KeyKind::Press | KeyKind::Repeat })) => match (mode, key) {
+				(_, Key::Special(SpecialKey::Up))    => self.move_cursor( 0, -1),
+				(_, Key::Special(SpecialKey::Down))  => self.move_cursor( 0,  1),
+				(_, Key::Special(SpecialKey::Left))  => self.move_cursor(-1,  0),
+				(_, Key::Special(SpecialKey::Right)) => self.move_cursor( 1,  0),
+
+				(Mode::Insert, Key::Char(c)) => self.type_char(c),
+				(Mode::Insert, Key::Special(SpecialKey::Backspace)) => self.backspace(),
+				(Mode::Insert, Key::Special(SpecialKey::Delete))    => self.delete(),
+				_ => {}
+			},
+			(Mode::Insert, Event::Paste(data)) => self.insert(&normalize_newlines(&data)),
 			_ => {}
 		}
 	}
-} impl<'a> IntoIterator for &'a Buffer {
+}
+
+///Returns the key a file's undo history is stored under. Symlinks are resolved so every name for a file shares a history.
+fn undo_key(file: &File) -> String {
+	match std::fs::canonicalize(file.path()) {
+		Ok(path) => path.to_string_lossy().into_owned(),
+		Err(_) => file.path().to_string(),
+	}
+}
+
+///Terminals send pasted line breaks as `\r`; this converts them (and any `\r\n` pairs) into `\n`.
+fn normalize_newlines(data: &[u8]) -> Vec<u8> {
+	let mut out = Vec::with_capacity(data.len());
+	let mut bytes = data.iter().peekable();
+	while let Some(byte) = bytes.next() {
+		if *byte == b'\r' {
+			bytes.next_if_eq(&&b'\n');
+			out.push(b'\n');
+		} else {
+			out.push(*byte);
+		}
+	}
+
+	out
+}
+
+impl<'a> IntoIterator for &'a Buffer {
 	type IntoIter = RopeIterator<'a>;
 	type Item = u8;
 	fn into_iter(self) -> Self::IntoIter {
 		self.rope.into_iter()
 	}
 }
+
+
+#[cfg(test)]
+mod test {
+	use regex::bytes::Regex;
+
+	use super::{Buffer, Config, Selection, SelectionPoint};
+	use crate::{editor::{Mode, Source}, terminil::input::{Event, SpecialKey}};
+
+	fn buffer(text: &str) -> Buffer {
+		Buffer::new(Source::Scratch(text.as_bytes().to_vec())).unwrap()
+	}
+
+	fn contents(b: &Buffer) -> String {
+		String::from_utf8(b.rope().clone().collapse()).unwrap()
+	}
+
+	fn type_text(b: &mut Buffer, text: &str) {
+		for c in text.chars() {
+			b.trigger_event(Event::Key(c.into()), &Mode::Insert);
+		}
+	}
+
+	#[test]
+	fn move_horizontally() {
+		let mut b = buffer("ab\ncd");
+
+		b.move_cursor(1, 0);
+		assert_eq!(b.cursor(), (0, 1));
+		b.move_cursor(2, 0);
+		assert_eq!(b.cursor(), (1, 0));
+		b.move_cursor(-1, 0);
+		assert_eq!(b.cursor(), (0, 2));
+	}
+
+	#[test]
+	fn clamp_at_ends() {
+		let mut b = buffer("ab\ncd");
+
+		b.move_cursor(-1, 0);
+		assert_eq!(b.cursor(), (0, 0));
+		b.move_cursor(0, -1);
+		assert_eq!(b.cursor(), (0, 0));
+		b.move_cursor(100, 0);
+		assert_eq!(b.cursor(), (1, 2));
+		b.move_cursor(0, 5);
+		assert_eq!(b.cursor(), (1, 2));
+	}
+
+	#[test]
+	fn keep_target_col() {
+		let mut b = buffer("long line\nab\r\nanother line");
+
+		b.move_cursor(6, 0);
+		b.move_cursor(0, 1);
+		assert_eq!(b.cursor(), (1, 2));
+		b.move_cursor(0, 1);
+		assert_eq!(b.cursor(), (2, 6));
+
+		//Moving horizontally resets the target column.
+		b.move_cursor(0, -1);
+		b.move_cursor(-1, 0);
+		b.move_cursor(0, 1);
+		assert_eq!(b.cursor(), (2, 1));
+	}
+
+	#[test]
+	fn move_over_clusters() {
+		let mut b = buffer("e\u{301}ñ\nxyz");
+
+		b.move_cursor(1, 0);
+		assert_eq!(b.cursor(), (0, 2));
+		b.move_cursor(1, 0);
+		assert_eq!(b.cursor(), (0, 3));
+
+		//Column 1 of the first line is inside a cluster, so the cursor snaps to its start.
+		b.move_cursor(0, 1);
+		b.move_cursor(-2, 0);
+		b.move_cursor(0, -1);
+		assert_eq!(b.cursor(), (0, 0));
+	}
+
+	#[test]
+	fn move_range_end() {
+		let mut b = buffer("ab\ncd");
+		b.selections = vec![Selection::Range { start_line: 0, start_col: 1, end: SelectionPoint { line: 0, col: 1, target_col: 1 } }];
+
+		b.move_cursor(0, 1);
+		b.move_cursor(1, 0);
+		assert_eq!(b.selections[0], Selection::Range { start_line: 0, start_col: 1, end: SelectionPoint { line: 1, col: 2, target_col: 2 } });
+	}
+
+	#[test]
+	fn insert_text() {
+		let mut b = buffer("world");
+
+		type_text(&mut b, "hello\n");
+		assert_eq!(contents(&b), "hello\nworld");
+		assert_eq!(b.cursor(), (1, 0));
+
+		b.trigger_event(Event::Key('x'.into()), &Mode::Normal);
+		assert_eq!(contents(&b), "hello\nworld");
+	}
+
+	#[test]
+	fn delete_text() {
+		let mut b = buffer("ab\ncd");
+
+		b.move_cursor(0, 1);
+		b.trigger_event(Event::Key(SpecialKey::Backspace.into()), &Mode::Insert);
+		assert_eq!(contents(&b), "abcd");
+		assert_eq!(b.cursor(), (0, 2));
+
+		b.trigger_event(Event::Key(SpecialKey::Delete.into()), &Mode::Insert);
+		assert_eq!(contents(&b), "abd");
+		assert_eq!(b.cursor(), (0, 2));
+
+		b.move_cursor(-2, 0);
+		b.trigger_event(Event::Key(SpecialKey::Backspace.into()), &Mode::Insert);
+		assert_eq!(contents(&b), "abd");
+		assert_eq!(b.cursor(), (0, 0));
+	}
+
+	#[test]
+	fn delete_clusters() {
+		let mut b = buffer("xe\u{301}y");
+
+		b.move_cursor(2, 0);
+		b.trigger_event(Event::Key(SpecialKey::Backspace.into()), &Mode::Insert);
+		assert_eq!(contents(&b), "xy");
+	}
+
+	#[test]
+	fn paste() {
+		let mut b = buffer("[]");
+
+		b.move_cursor(1, 0);
+		b.trigger_event(Event::Paste(b"one\r\ntwo\rthree".to_vec()), &Mode::Insert);
+		assert_eq!(contents(&b), "[one\ntwo\nthree]");
+		assert_eq!(b.cursor(), (2, 5));
+	}
+
+	#[test]
+	fn edit_every_selection() {
+		let mut b = buffer("ab\ncd\nef");
+		b.selections = (0..3).map(|line| Selection::Point(SelectionPoint { line, col: 1, target_col: 1 })).collect();
+
+		type_text(&mut b, "-");
+		assert_eq!(contents(&b), "a-b\nc-d\ne-f");
+		assert_eq!(b.selections.len(), 3);
+		assert_eq!(b.cursor(), (0, 2));
+
+		b.trigger_event(Event::Key(SpecialKey::Backspace.into()), &Mode::Insert);
+		b.trigger_event(Event::Key(SpecialKey::Backspace.into()), &Mode::Insert);
+		assert_eq!(contents(&b), "b\nd\nf");
+	}
+
+	#[test]
+	fn merge_selections() {
+		let mut b = buffer("ab\ncd");
+		b.selections = (0..2).map(|line| Selection::Point(SelectionPoint { line, col: 1, target_col: 1 })).collect();
+		b.move_cursor(0, -1);
+
+		type_text(&mut b, "X");
+		assert_eq!(contents(&b), "aXb\ncd");
+		assert_eq!(b.selections.len(), 1);
+
+		//A cursor inside a range is swallowed by it.
+		b.selections = vec![
+			Selection::Range { start_line: 0, start_col: 0, end: SelectionPoint { line: 0, col: 3, target_col: 3 } },
+			Selection::Point(SelectionPoint { line: 0, col: 2, target_col: 2 }),
+		];
+		type_text(&mut b, "-");
+		assert_eq!(contents(&b), "-\ncd");
+		assert_eq!(b.selections.len(), 1);
+	}
+
+	#[test]
+	fn replace_range_selection() {
+		let mut b = buffer("hello world");
+		b.selections = vec![Selection::Range { start_line: 0, start_col: 6, end: SelectionPoint { line: 0, col: 11, target_col: 11 } }];
+
+		type_text(&mut b, "there");
+		assert_eq!(contents(&b), "hello there");
+		assert_eq!(b.selections, vec![Selection::Point(SelectionPoint { line: 0, col: 11, target_col: 11 })]);
+	}
+
+	#[test]
+	fn follow_cursor() {
+		let config = Config { scroll_margin: 2, side_scroll_margin: 3, ..Config::default() };
+		let mut b = buffer(&"a very long line of text\n".repeat(30));
+
+		b.move_cursor(0, 10);
+		b.scroll_to_cursor(10, 8, &config);
+		assert_eq!(b.viewport().top_line, 5);
+		b.move_cursor(0, -6);
+		b.scroll_to_cursor(10, 8, &config);
+		assert_eq!(b.viewport().top_line, 2);
+
+		b.move_cursor(20, 0);
+		b.scroll_to_cursor(10, 8, &config);
+		assert_eq!(b.viewport().left_col, 14);
+		b.move_cursor(-12, 0);
+		b.scroll_to_cursor(10, 8, &config);
+		assert_eq!(b.viewport().left_col, 5);
+
+		//Margins which don't fit are shrunk rather than making the view jump around.
+		b.scroll_to_cursor(10, 1, &config);
+		assert_eq!(b.viewport().top_line, 4);
+	}
+
+	#[test]
+	fn tab_stops() {
+		let config = Config { side_scroll_margin: 0, ..Config::default() };
+		let mut b = buffer("\tab\tc");
+
+		let mut cols = vec![b.cursor_display_col()];
+		for _ in 0..5 {
+			b.move_cursor(1, 0);
+			cols.push(b.cursor_display_col());
+		}
+		assert_eq!(cols, [0, 8, 9, 10, 16, 17]);
+
+		b.scroll_to_cursor(10, 1, &config);
+		assert_eq!(b.viewport().left_col, 8);
+		b.click(1, 0);
+		assert_eq!(b.cursor(), (0, 2));
+		b.click(5, 0);
+		assert_eq!(b.cursor(), (0, 3));
+		b.click(8, 0);
+		assert_eq!(b.cursor(), (0, 4));
+	}
+
+	#[test]
+	fn mouse_selection() {
+		let mut b = buffer("one\ntwo 世界\nthree");
+		b.click(2, 1);
+		assert_eq!(b.cursor(), (1, 2));
+		//Clicking either half of a wide character lands before it.
+		b.click(7, 1);
+		assert_eq!(b.cursor(), (1, 5));
+		b.click(40, 8);
+		assert_eq!(b.cursor(), (2, 5));
+
+		b.click(1, 0);
+		b.drag_to(1, 1);
+		assert_eq!(b.selection_ranges(), vec![1..5]);
+		b.drag_to(0, 0);
+		assert_eq!(b.selection_ranges(), vec![0..1]);
+		b.drag_to(1, 0);
+		assert_eq!(b.selections, vec![Selection::Point(SelectionPoint { line: 0, col: 1, target_col: 1 })]);
+	}
+
+	#[test]
+	fn jump_to_position() {
+		let mut b = buffer("one\ne\u{301}e\u{301}\nthree");
+		b.jump_to(2, 3);
+		assert_eq!(b.cursor(), (2, 3));
+		//Positions inside a grapheme cluster land before it.
+		b.jump_to(1, 3);
+		assert_eq!(b.cursor(), (1, 2));
+		b.jump_to(9, 9);
+		assert_eq!(b.cursor(), (2, 5));
+	}
+
+	#[test]
+	fn search_and_substitute() {
+		let mut b = buffer("let a = 1;\nlet b = a + a;\n");
+		let regex = Regex::new(r"\ba\b").unwrap();
+		assert!(b.search(&regex));
+		assert_eq!(b.cursor(), (0, 4));
+		assert!(b.search(&regex));
+		assert_eq!(b.cursor(), (1, 8));
+		b.search(&regex);
+		assert!(b.search(&regex));
+		assert_eq!(b.cursor(), (0, 4));
+		assert!(!b.search(&Regex::new("c").unwrap()));
+
+		assert_eq!(b.substitute(&regex, b"x", false), 2);
+		assert_eq!(contents(&b), "let x = 1;\nlet b = x + a;\n");
+		assert_eq!(b.substitute(&Regex::new(r"let (\w)").unwrap(), b"const ${1}_", true), 2);
+		assert_eq!(contents(&b), "const x_ = 1;\nconst b_ = x + a;\n");
+
+		b.undo();
+		b.undo();
+		assert_eq!(contents(&b), "let a = 1;\nlet b = a + a;\n");
+	}
+
+	#[test]
+	fn delete_lines() {
+		let mut b = buffer("one\ntwo\nthree\nfour");
+		b.jump_to(1, 2);
+		b.delete_lines(1);
+		assert_eq!((contents(&b).as_str(), b.cursor()), ("one\nthree\nfour", (1, 0)));
+		b.delete_lines(5);
+		assert_eq!((contents(&b).as_str(), b.cursor()), ("one", (0, 0)));
+		b.delete_lines(1);
+		assert_eq!(contents(&b), "");
+		b.undo();
+		assert_eq!(contents(&b), "one");
+	}
+
+	#[test]
+	fn scroll_with_cursor() {
+		let config = Config { scroll_margin: 2, ..Config::default() };
+		let mut b = buffer(&"line\n".repeat(50));
+
+		b.scroll_by(10, 8, &config);
+		assert_eq!((b.viewport().top_line, b.cursor().0), (10, 12));
+		b.scroll_by(-4, 8, &config);
+		assert_eq!((b.viewport().top_line, b.cursor().0), (6, 11));
+		b.scroll_by(-10, 8, &config);
+		assert_eq!((b.viewport().top_line, b.cursor().0), (0, 5));
+		b.scroll_by(100, 8, &config);
+		assert_eq!((b.viewport().top_line, b.cursor().0), (50, 50));
+	}
+
+	#[test]
+	fn track_modifications() {
+		let mut b = buffer("text");
+		assert!(!b.is_modified());
+
+		//Deleting nothing isn't a modification.
+		b.trigger_event(Event::Key(SpecialKey::Backspace.into()), &Mode::Insert);
+		assert!(!b.is_modified());
+
+		type_text(&mut b, "more ");
+		assert!(b.is_modified());
+	}
+
+	#[test]
+	fn undo_redo() {
+		let mut b = buffer("");
+
+		type_text(&mut b, "hello world");
+		assert_eq!(contents(&b), "hello world");
+		assert!(b.undo());
+		assert_eq!(contents(&b), "hello ");
+		assert_eq!(b.cursor(), (0, 6));
+		assert!(b.undo());
+		assert_eq!(contents(&b), "");
+		assert!(!b.undo());
+
+		assert!(b.redo());
+		assert_eq!(contents(&b), "hello ");
+		assert!(b.redo());
+		assert_eq!(contents(&b), "hello world");
+		assert_eq!(b.cursor(), (0, 11));
+		assert!(!b.redo());
+	}
+
+	#[test]
+	fn undo_groups() {
+		let mut b = buffer("ab\ncd");
+		b.selections = (0..2).map(|line| Selection::Point(SelectionPoint { line, col: 2, target_col: 2 })).collect();
+
+		//One keypress across several cursors is one step.
+		type_text(&mut b, "!");
+		b.move_cursor(0, 0);
+		b.trigger_event(Event::Paste(b"xyz".to_vec()), &Mode::Insert);
+		b.trigger_event(Event::Key(SpecialKey::Backspace.into()), &Mode::Insert);
+		b.trigger_event(Event::Key(SpecialKey::Backspace.into()), &Mode::Insert);
+		assert_eq!(contents(&b), "ab!x\ncd!x");
+
+		assert!(b.undo());
+		assert_eq!(contents(&b), "ab!xyz\ncd!xyz");
+		assert!(b.undo());
+		assert_eq!(contents(&b), "ab!\ncd!");
+		assert!(b.undo());
+		assert_eq!(contents(&b), "ab\ncd");
+		assert_eq!(b.selections.len(), 2);
+		assert_eq!(b.cursor(), (0, 2));
+	}
+
+	#[test]
+	fn undo_tree() {
+		let mut b = buffer("");
+
+		type_text(&mut b, "one ");
+		type_text(&mut b, "two ");
+		b.undo();
+		type_text(&mut b, "three ");
+		assert_eq!(contents(&b), "one three ");
+
+		//Redo follows the newest branch, but the old one is still reachable chronologically.
+		assert!(!b.redo());
+		assert_eq!(b.time_travel(-1), 1);
+		assert_eq!(contents(&b), "one two ");
+		assert_eq!(b.time_travel(1), 1);
+		assert_eq!(contents(&b), "one three ");
+		assert_eq!(b.time_travel(-10), 3);
+		assert_eq!(contents(&b), "");
+	}
+
+	#[test]
+	fn undo_to_saved() {
+		let mut b = buffer("text");
+
+		type_text(&mut b, "more");
+		assert!(b.is_modified());
+		b.undo();
+		assert!(!b.is_modified());
+	}
+}
@@ -0,0 +1,196 @@
+//! Commands which can be typed at the `:` prompt.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+	///Saves the current buffer. When a path is given, the buffer is written there and uses it from then on.
+	Write(Option<String>),
+	///Closes the editor. Unless `force` is set, this is refused while any buffer has unsaved changes.
+	Quit { force: bool },
+	///Saves the current buffer and then quits if that succeeded.
+	WriteQuit,
+	Undo,
+	Redo,
+	///Moves backwards through the current buffer's history by this many revisions, in the order they were made.
+	///Unlike undo, this can reach changes on other branches of the undo tree.
+	Earlier(usize),
+	///Moves forwards through the current buffer's history by this many revisions, in the order they were made.
+	Later(usize),
+	///Hands the terminal back to the shell and stops the editor until it's continued, like Ctrl-Z in other programs.
+	Suspend,
+	///Switches to another open buffer, this many buffers forwards (or backwards if negative), wrapping around.
+	SwitchBuffer(isize),
+	///Moves the cursor to the start of this zero-indexed line.
+	Goto(usize),
+	///Moves the cursor to the next match of a regex, wrapping around the end of the buffer.
+	Search(String),
+	///Replaces matches of `pattern` on every line of the buffer. `replacement` can refer to capture groups as `$1` or
+	///`${name}`. Only the first match on each line is replaced unless `global` is set.
+	Substitute { pattern: String, replacement: String, global: bool },
+	///Inserts text at every cursor.
+	Insert(String),
+	///Deletes this many lines, starting from the cursor's.
+	DeleteLines(usize),
+	///Writes the buffer to stdout. Only possible in batch mode, where stdout isn't showing the editor.
+	Print,
+} impl Command {
+	pub fn parse(input: &str) -> Result<Command, String> {
+		let input = input.trim();
+		//These take patterns rather than arguments, which can contain spaces.
+		if let Some(pattern) = input.strip_prefix('/') {
+			let (pattern, _) = split_pattern(pattern);
+			return Ok(Command::Search(pattern));
+		}
+		if let Some(rest) = input.strip_prefix("s/") {
+			let (pattern, rest) = split_pattern(rest);
+			let (replacement, flags) = split_pattern(rest.unwrap_or_default());
+			return match flags.unwrap_or_default() {
+				"" => Ok(Command::Substitute { pattern, replacement, global: false }),
+				"g" => Ok(Command::Substitute { pattern, replacement, global: true }),
+				flags => Err(format!("Unknown substitute flags: {flags}")),
+			};
+		}
+		if !input.is_empty() && input.bytes().all(|b| b.is_ascii_digit()) {
+			return Ok(Command::Goto(parse_count(input)?.saturating_sub(1)));
+		}
+		let (name, args) = input.split_once(char::is_whitespace).map_or((input, ""), |(name, args)| (name, args.trim()));
+
+		match name {
+			"w" | "write" => Ok(Command::Write(if args.is_empty() { None } else { Some(args.to_string()) })),
+			"saveas" => if args.is_empty() {
+				Err("saveas needs a file name".to_string())
+			} else {
+				Ok(Command::Write(Some(args.to_string())))
+			},
+			"q" | "quit" => Ok(Command::Quit { force: false }),
+			"q!" | "quit!" => Ok(Command::Quit { force: true }),
+			"wq" | "x" => Ok(Command::WriteQuit),
+			"u" | "undo" => Ok(Command::Undo),
+			"redo" => Ok(Command::Redo),
+			"earlier" => Ok(Command::Earlier(parse_count(args)?)),
+			"later" => Ok(Command::Later(parse_count(args)?)),
+			"suspend" | "stop" => Ok(Command::Suspend),
+			"bn" | "bnext" => Ok(Command::SwitchBuffer(parse_count(args)? as isize)),
+			"bp" | "bprev" => Ok(Command::SwitchBuffer(-(parse_count(args)? as isize))),
+			"insert" => Ok(Command::Insert(unescape(args))),
+			"d" | "delete" => Ok(Command::DeleteLines(parse_count(args)?)),
+			"p" | "print" => Ok(Command::Print),
+			"" => Err("No command given".to_string()),
+			_ => Err(format!("Unknown command: {name}")),
+		}
+	}
+}
+
+///Parses an optional count argument, which defaults to 1.
+fn parse_count(args: &str) -> Result<usize, String> {
+	if args.is_empty() {
+		Ok(1)
+	} else {
+		args.parse().map_err(|_| format!("Expected a number, found \"{args}\""))
+	}
+}
+
+///Splits a `/`-terminated part off the start of a search or substitute command, returning it and whatever follows the
+///`/` (or `None` if it was left off). A `\/` in the part stands for `/`, and other escapes are left for the regex.
+fn split_pattern(input: &str) -> (String, Option<&str>) {
+	let mut part = String::new();
+	let mut chars = input.char_indices();
+	while let Some((i, c)) = chars.next() {
+		match c {
+			'/' => return (part, Some(&input[i + 1..])),
+			'\\' => match chars.next() {
+				Some((_, '/')) => part.push('/'),
+				Some((_, c)) => {
+					part.push('\\');
+					part.push(c);
+				},
+				None => part.push('\\'),
+			},
+			c => part.push(c),
+		}
+	}
+
+	(part, None)
+}
+
+///Replaces the escapes `\n`, `\t` and `\\` with the characters they stand for, so that text given on one line can
+///contain line breaks.
+fn unescape(text: &str) -> String {
+	let mut out = String::with_capacity(text.len());
+	let mut chars = text.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			out.push(c);
+			continue;
+		}
+		match chars.next() {
+			Some('n') => out.push('\n'),
+			Some('t') => out.push('\t'),
+			Some(other) => out.push(other),
+			None => out.push('\\'),
+		}
+	}
+
+	out
+}
+
+#[cfg(test)]
+mod test {
+	use super::Command;
+
+	#[test]
+	fn parse_write() {
+		assert_eq!(Command::parse("w"), Ok(Command::Write(None)));
+		assert_eq!(Command::parse(" write  notes.txt "), Ok(Command::Write(Some("notes.txt".to_string()))));
+		assert_eq!(Command::parse("saveas a b.txt"), Ok(Command::Write(Some("a b.txt".to_string()))));
+		assert!(Command::parse("saveas").is_err());
+	}
+
+	#[test]
+	fn parse_quit() {
+		assert_eq!(Command::parse("q"), Ok(Command::Quit { force: false }));
+		assert_eq!(Command::parse("quit!"), Ok(Command::Quit { force: true }));
+		assert_eq!(Command::parse("wq"), Ok(Command::WriteQuit));
+	}
+
+	#[test]
+	fn parse_history() {
+		assert_eq!(Command::parse("undo"), Ok(Command::Undo));
+		assert_eq!(Command::parse("earlier"), Ok(Command::Earlier(1)));
+		assert_eq!(Command::parse("later 4"), Ok(Command::Later(4)));
+		assert!(Command::parse("later four").is_err());
+	}
+
+	#[test]
+	fn parse_suspend() {
+		assert_eq!(Command::parse("suspend"), Ok(Command::Suspend));
+		assert_eq!(Command::parse("stop"), Ok(Command::Suspend));
+	}
+
+	#[test]
+	fn parse_buffers() {
+		assert_eq!(Command::parse("bn"), Ok(Command::SwitchBuffer(1)));
+		assert_eq!(Command::parse("bprev 2"), Ok(Command::SwitchBuffer(-2)));
+	}
+
+	#[test]
+	fn parse_batch_commands() {
+		assert_eq!(Command::parse("12"), Ok(Command::Goto(11)));
+		assert_eq!(Command::parse("/a b\\/c/"), Ok(Command::Search("a b/c".to_string())));
+		assert_eq!(Command::parse("s/(\\w+) x/$1/g"), Ok(Command::Substitute {
+			pattern: "(\\w+) x".to_string(),
+			replacement: "$1".to_string(),
+			global: true,
+		}));
+		assert_eq!(Command::parse("s/a/b"), Ok(Command::Substitute { pattern: "a".to_string(), replacement: "b".to_string(), global: false }));
+		assert!(Command::parse("s/a/b/q").is_err());
+		assert_eq!(Command::parse("insert a\\nb\\\\"), Ok(Command::Insert("a\nb\\".to_string())));
+		assert_eq!(Command::parse("d 3"), Ok(Command::DeleteLines(3)));
+		assert_eq!(Command::parse("print"), Ok(Command::Print));
+	}
+
+	#[test]
+	fn parse_unknown() {
+		assert!(Command::parse("").is_err());
+		assert!(Command::parse("frobnicate").is_err());
+	}
+}
@@ -0,0 +1,25 @@
+use serde::Deserialize;
+
+///Editor behaviour which isn't part of the theme. Every setting has a default, so a config file only needs to list the
+///ones it changes.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+	///How many lines are kept visible above and below the cursor when scrolling.
+	pub scroll_margin: usize,
+	///How many columns are kept visible to the left and right of the cursor when scrolling sideways.
+	pub side_scroll_margin: usize,
+	///How many lines each step of the mouse wheel scrolls by.
+	pub wheel_scroll_lines: usize,
+	///The theme to use, by name from the themes directory or as a path. The built-in theme is used if this is unset.
+	pub theme: Option<String>,
+} impl Default for Config {
+	fn default() -> Self {
+		Config {
+			scroll_margin: 3,
+			side_scroll_margin: 5,
+			wheel_scroll_lines: 3,
+			theme: None,
+		}
+	}
+}
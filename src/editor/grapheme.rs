@@ -0,0 +1,93 @@
+//! Splits text into extended grapheme clusters, which is what a user perceives as a single character.
+//!
+//! This follows the regex formulation of extended grapheme clusters given in
+//! [UAX #29](https://www.unicode.org/reports/tr29/#Regex_Definitions), using the Unicode tables
+//! bundled with the `regex` crate.
+
+use std::sync::OnceLock;
+
+use regex::bytes::Regex;
+
+use crate::terminil::width;
+
+static CLUSTER: OnceLock<Regex> = OnceLock::new();
+
+fn cluster_regex() -> &'static Regex {
+	CLUSTER.get_or_init(|| Regex::new(r"(?x)^(?:
+		\r\n
+		| \p{gcb=Control}
+		| \p{gcb=Prepend}*
+			(?:
+				\p{gcb=RI}\p{gcb=RI}
+				| \p{gcb=L}*(?:\p{gcb=V}+|\p{gcb=LV}\p{gcb=V}*|\p{gcb=LVT})\p{gcb=T}*
+				| \p{gcb=L}+
+				| \p{gcb=T}+
+				| \p{Extended_Pictographic}(?:\p{gcb=Extend}*\p{gcb=ZWJ}\p{Extended_Pictographic})*
+				| [^\p{gcb=Control}\r\n]
+			)
+			[\p{gcb=Extend}\p{gcb=ZWJ}\p{gcb=SpacingMark}]*
+	)").expect("this to be a valid regex"))
+}
+
+///Returns the length in bytes of the grapheme cluster at the start of `text`.
+///
+///Bytes which aren't valid UTF-8 are treated as clusters of their own.
+pub fn cluster_len(text: &[u8]) -> usize {
+	if text.is_empty() {
+		0
+	} else {
+		cluster_regex().find(text).map_or(1, |m| m.end())
+	}
+}
+
+///Iterator over the grapheme clusters of a byte slice.
+pub struct Clusters<'a> {
+	text: &'a [u8],
+} impl<'a> Iterator for Clusters<'a> {
+	type Item = &'a [u8];
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.text.is_empty() {
+			None
+		} else {
+			let (cluster, rest) = self.text.split_at(cluster_len(self.text));
+			self.text = rest;
+
+			Some(cluster)
+		}
+	}
+}
+
+pub fn clusters(text: &[u8]) -> Clusters<'_> {
+	Clusters { text }
+}
+
+///Returns the number of screen cells a grapheme cluster takes up.
+pub fn display_width(cluster: &[u8]) -> usize {
+	width::width(&String::from_utf8_lossy(cluster))
+}
+
+///How many columns apart tab stops are, which is the terminal's default.
+pub const TAB_WIDTH: usize = 8;
+
+///Returns the display column just past `cluster` when it's drawn at column `col`. Tabs reach up to the next tab stop.
+pub fn next_col(col: usize, cluster: &[u8]) -> usize {
+	if cluster == b"\t" {
+		(col / TAB_WIDTH + 1) * TAB_WIDTH
+	} else {
+		col + display_width(cluster)
+	}
+}
+
+///Returns true if `byte` starts a character, which every byte other than a UTF-8 continuation byte does.
+///
+///Continuation bytes always belong to the character before them, even when they don't form a valid sequence with it,
+///so a character is one byte which isn't a continuation byte followed by every continuation byte after it. This lets
+///characters be counted without looking past the bytes at hand.
+pub fn is_char_start(byte: u8) -> bool {
+	byte & 0b1100_0000 != 0b1000_0000
+}
+
+///Returns the number of characters (as defined by [is_char_start]) starting within `bytes`.
+pub fn count_chars(bytes: &[u8]) -> usize {
+	bytes.iter().filter(|b| is_char_start(**b)).count()
+}
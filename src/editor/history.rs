@@ -0,0 +1,433 @@
+//! Undo history for a [Buffer](super::buffer::Buffer).
+//!
+//! Every change to a buffer is recorded as a [Transaction]. Transactions are kept in a tree rather than a stack: undoing
+//! and then making a new change starts a new branch instead of discarding the changes which were undone, and those
+//! branches can still be reached by moving through revisions chronologically.
+//!
+//! Histories can also be stored in a cache directory so that they outlive the editor session. A stored history is
+//! only loaded again if the file on disk still has the contents it was stored with.
+
+use std::{fs::{DirBuilder, OpenOptions}, io::Write, os::unix::fs::{DirBuilderExt, OpenOptionsExt}, path::{Path, PathBuf}};
+
+use super::{buffer::Selection, rope::Rope, xdg_dir};
+
+///Identifies undo files, and their format version.
+const MAGIC: &[u8; 8] = b"EDRUNDO1";
+
+///A single replacement within a buffer, with enough information to reverse it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+	pub start: usize,
+	pub deleted: Vec<u8>,
+	pub inserted: Vec<u8>,
+} impl Edit {
+	fn apply(&self, rope: &mut Rope) {
+		rope.replace_range(self.start..self.start + self.deleted.len(), &self.inserted);
+	}
+	fn revert(&self, rope: &mut Rope) {
+		rope.replace_range(self.start..self.start + self.inserted.len(), &self.deleted);
+	}
+}
+
+///A group of edits which are undone and redone as one step, along with the selections on either side of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+	///Edits in the order they were made. Each edit's offsets refer to the text as it was after the previous edit.
+	pub edits: Vec<Edit>,
+	pub selections_before: Vec<Selection>,
+	pub selections_after: Vec<Selection>,
+} impl Transaction {
+	pub fn apply(&self, rope: &mut Rope) {
+		for edit in &self.edits {
+			edit.apply(rope);
+		}
+	}
+	pub fn revert(&self, rope: &mut Rope) {
+		for edit in self.edits.iter().rev() {
+			edit.revert(rope);
+		}
+	}
+}
+
+///Kinds of edit which are merged into a single transaction when they happen back to back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Group {
+	Typing,
+	Deleting,
+}
+
+#[derive(Debug, Clone)]
+struct Revision {
+	parent: usize,
+	///The transaction which leads from the parent to this revision. The root revision's is empty.
+	transaction: Transaction,
+	///The child which was most recently visited, which is where redo goes.
+	last_child: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+	///Revisions in the order they were created. The first is the root, which is the text as it was opened.
+	revisions: Vec<Revision>,
+	current: usize,
+	///The kind of the last transaction, if more edits of the same kind should be merged into it.
+	open_group: Option<Group>,
+} impl History {
+	pub fn new() -> Self {
+		History {
+			revisions: vec![Revision {
+				parent: 0,
+				transaction: Transaction { edits: Vec::new(), selections_before: Vec::new(), selections_after: Vec::new() },
+				last_child: None,
+			}],
+			current: 0,
+			open_group: None,
+		}
+	}
+
+	///Returns an identifier for the current revision. Identifiers increase in the order revisions were created.
+	pub fn current(&self) -> usize {
+		self.current
+	}
+
+	///Records a transaction which has already been applied to the buffer.
+	///
+	///If `group` matches the group of the previous transaction (and nothing has happened in between), the edits are
+	///merged into it so they are undone together.
+	pub fn record(&mut self, transaction: Transaction, group: Option<Group>) {
+		let is_newest = self.current == self.revisions.len() - 1 && self.current != 0;
+		if group.is_some() && group == self.open_group && is_newest {
+			let previous = &mut self.revisions[self.current].transaction;
+			previous.edits.extend(transaction.edits);
+			previous.selections_after = transaction.selections_after;
+			return;
+		}
+
+		self.revisions.push(Revision {
+			parent: self.current,
+			transaction,
+			last_child: None,
+		});
+		let new = self.revisions.len() - 1;
+		self.revisions[self.current].last_child = Some(new);
+		self.current = new;
+		self.open_group = group;
+	}
+
+	///Stops later edits from being merged into the current transaction.
+	pub fn close_group(&mut self) {
+		self.open_group = None;
+	}
+
+	///Moves back one revision, returning the transaction which must be reverted.
+	pub fn undo(&mut self) -> Option<&Transaction> {
+		self.close_group();
+		if self.current == 0 {
+			return None;
+		}
+		let undone = self.current;
+		self.current = self.revisions[undone].parent;
+		self.revisions[self.current].last_child = Some(undone);
+
+		Some(&self.revisions[undone].transaction)
+	}
+
+	///Moves forward one revision along the most recently visited branch, returning the transaction which must be applied.
+	pub fn redo(&mut self) -> Option<&Transaction> {
+		self.close_group();
+		let child = self.revisions[self.current].last_child?;
+		self.current = child;
+
+		Some(&self.revisions[child].transaction)
+	}
+
+	///Moves to the revision with the given identifier, which may be on another branch.
+	///
+	///Returns the transactions to revert followed by the transactions to apply, both in the order they must happen.
+	pub fn goto(&mut self, target: usize) -> (Vec<&Transaction>, Vec<&Transaction>) {
+		self.close_group();
+		let target = target.min(self.revisions.len() - 1);
+
+		let ancestors = |mut revision: usize| {
+			let mut path = vec![revision];
+			while revision != 0 {
+				revision = self.revisions[revision].parent;
+				path.push(revision);
+			}
+			path
+		};
+		let from = ancestors(self.current);
+		let to = ancestors(target);
+		let common = *from.iter().find(|r| to.contains(r)).expect("all revisions to share the root");
+
+		let undo: Vec<usize> = from.into_iter().take_while(|r| *r != common).collect();
+		let mut redo: Vec<usize> = to.into_iter().take_while(|r| *r != common).collect();
+		redo.reverse();
+
+		for revision in &redo {
+			let parent = self.revisions[*revision].parent;
+			self.revisions[parent].last_child = Some(*revision);
+		}
+		self.current = target;
+
+		(
+			undo.into_iter().map(|r| &self.revisions[r].transaction).collect(),
+			redo.into_iter().map(|r| &self.revisions[r].transaction).collect(),
+		)
+	}
+
+	///Returns the identifier of the newest revision.
+	pub fn newest(&self) -> usize {
+		self.revisions.len() - 1
+	}
+
+	///Encodes the history for storage. `saved` is the revision whose text matches the file on disk, which has the
+	///given content hash; it becomes the current revision when the history is decoded.
+	fn encode(&self, path: &str, saved: usize, content_hash: u64) -> Vec<u8> {
+		let mut out = MAGIC.to_vec();
+		write_bytes(&mut out, path.as_bytes());
+		write_u64(&mut out, content_hash);
+		write_u64(&mut out, saved as u64);
+		write_u64(&mut out, self.revisions.len() as u64);
+
+		for revision in &self.revisions {
+			write_u64(&mut out, revision.parent as u64);
+			write_u64(&mut out, revision.last_child.map_or(0, |c| c as u64 + 1));
+
+			let transaction = &revision.transaction;
+			write_u64(&mut out, transaction.edits.len() as u64);
+			for edit in &transaction.edits {
+				write_u64(&mut out, edit.start as u64);
+				write_bytes(&mut out, &edit.deleted);
+				write_bytes(&mut out, &edit.inserted);
+			}
+			for selections in [&transaction.selections_before, &transaction.selections_after] {
+				write_u64(&mut out, selections.len() as u64);
+				for selection in selections {
+					selection.encode(&mut out);
+				}
+			}
+		}
+
+		out
+	}
+
+	///Decodes a history created by [History::encode], returning `None` if the data is malformed or was stored for a
+	///different path or content hash.
+	fn decode(data: &[u8], path: &str, content_hash: u64) -> Option<History> {
+		let mut reader = Reader(data.strip_prefix(MAGIC)?);
+		if reader.bytes()? != path.as_bytes() || reader.u64()? != content_hash {
+			return None;
+		}
+		let current = reader.usize()?;
+
+		let count = reader.usize()?;
+		let mut revisions = Vec::new();
+		for index in 0..count {
+			let parent = reader.usize()?;
+			let last_child = reader.usize()?.checked_sub(1);
+			//Parents are always created before their children.
+			if (index != 0 && parent >= index) || last_child.is_some_and(|c| c <= index || c >= count) {
+				return None;
+			}
+
+			let mut edits = Vec::new();
+			for _ in 0..reader.usize()? {
+				edits.push(Edit {
+					start: reader.usize()?,
+					deleted: reader.bytes()?,
+					inserted: reader.bytes()?,
+				});
+			}
+			let mut selections = [Vec::new(), Vec::new()];
+			for list in &mut selections {
+				for _ in 0..reader.usize()? {
+					list.push(Selection::decode(&mut reader)?);
+				}
+			}
+			let [selections_before, selections_after] = selections;
+
+			revisions.push(Revision {
+				parent,
+				transaction: Transaction { edits, selections_before, selections_after },
+				last_child,
+			});
+		}
+		if revisions.is_empty() || current >= revisions.len() || !reader.0.is_empty() {
+			return None;
+		}
+
+		Some(History {
+			revisions,
+			current,
+			open_group: None,
+		})
+	}
+
+	///Stores the history for the file at `path` within `dir`. See [History::encode] for the meaning of `saved` and
+	///`content_hash`.
+	///
+	///Histories hold the text of the file, so only the user can read them, however private the file itself is.
+	pub fn store(&self, dir: &Path, path: &str, saved: usize, content_hash: u64) -> Result<(), std::io::Error> {
+		DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+		let undo_path = undo_file(dir, path);
+		//Editors storing the same history at once each write their own temp file. One with this name can only be left
+		//over from a crash.
+		let temp_path = undo_path.with_extension(format!("{}.tmp", std::process::id()));
+		let _ = std::fs::remove_file(&temp_path);
+
+		let result = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&temp_path)
+			.and_then(|mut file| file.write_all(&self.encode(path, saved, content_hash)))
+			.and_then(|()| std::fs::rename(&temp_path, &undo_path));
+		if result.is_err() {
+			let _ = std::fs::remove_file(&temp_path);
+		}
+
+		result
+	}
+
+	///Loads the history stored for the file at `path`, if the file still has the given content hash.
+	///
+	///Histories which don't match (because the file was changed by another program) or can't be read are deleted.
+	pub fn load(dir: &Path, path: &str, content_hash: u64) -> Option<History> {
+		let undo_path = undo_file(dir, path);
+		let data = std::fs::read(&undo_path).ok()?;
+
+		let history = History::decode(&data, path, content_hash);
+		if history.is_none() {
+			let _ = std::fs::remove_file(&undo_path);
+		}
+
+		history
+	}
+}
+
+///Returns the directory persistent undo histories are stored in, following the XDG base directory specification.
+pub fn undo_dir() -> Option<PathBuf> {
+	Some(xdg_dir("XDG_CACHE_HOME", ".cache")?.join("undo"))
+}
+
+fn undo_file(dir: &Path, path: &str) -> PathBuf {
+	dir.join(format!("{:016x}", hash_bytes(FNV_OFFSET, path.as_bytes())))
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+///Extends a 64-bit FNV-1a hash. This is used instead of `std::hash` because stored hashes must stay the same between builds.
+fn hash_bytes(mut hash: u64, bytes: &[u8]) -> u64 {
+	for byte in bytes {
+		hash ^= *byte as u64;
+		hash = hash.wrapping_mul(0x100000001b3);
+	}
+
+	hash
+}
+
+///Hashes the contents of a rope, for checking whether a stored history still applies to a file.
+pub fn content_hash(rope: &Rope) -> u64 {
+	rope.chunks().fold(FNV_OFFSET, hash_bytes)
+}
+
+pub fn write_u64(out: &mut Vec<u8>, value: u64) {
+	out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+	write_u64(out, bytes.len() as u64);
+	out.extend_from_slice(bytes);
+}
+
+///Reads values written by [write_u64] and [write_bytes], returning `None` once the data runs out.
+pub struct Reader<'a>(&'a [u8]);
+impl<'a> Reader<'a> {
+	pub fn u64(&mut self) -> Option<u64> {
+		let (value, rest) = self.0.split_first_chunk::<8>()?;
+		self.0 = rest;
+
+		Some(u64::from_le_bytes(*value))
+	}
+	pub fn usize(&mut self) -> Option<usize> {
+		usize::try_from(self.u64()?).ok()
+	}
+	fn bytes(&mut self) -> Option<Vec<u8>> {
+		let len = self.usize()?;
+		if len > self.0.len() {
+			return None;
+		}
+		let (bytes, rest) = self.0.split_at(len);
+		self.0 = rest;
+
+		Some(bytes.to_vec())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::os::unix::fs::PermissionsExt;
+
+	use super::{content_hash, undo_file, Edit, Group, History, Transaction};
+	use crate::editor::rope::Rope;
+
+	fn transaction(start: usize, inserted: &[u8]) -> Transaction {
+		Transaction {
+			edits: vec![Edit { start, deleted: Vec::new(), inserted: inserted.to_vec() }],
+			selections_before: Vec::new(),
+			selections_after: Vec::new(),
+		}
+	}
+
+	fn sample() -> History {
+		let mut history = History::new();
+		history.record(transaction(0, b"one "), Some(Group::Typing));
+		history.record(transaction(4, b"two"), None);
+		history.undo();
+		history.record(transaction(4, b"three"), None);
+
+		history
+	}
+
+	#[test]
+	fn encode_round_trip() {
+		let history = sample();
+		let data = history.encode("notes.txt", 2, 1234);
+
+		let decoded = History::decode(&data, "notes.txt", 1234).unwrap();
+		assert_eq!(decoded.current(), 2);
+		assert_eq!(decoded.newest(), 3);
+		for (a, b) in decoded.revisions.iter().zip(&history.revisions) {
+			assert_eq!(a.parent, b.parent);
+			assert_eq!(a.last_child, b.last_child);
+			assert_eq!(a.transaction, b.transaction);
+		}
+	}
+
+	#[test]
+	fn reject_mismatches() {
+		let data = sample().encode("notes.txt", 2, 1234);
+
+		assert!(History::decode(&data, "other.txt", 1234).is_none());
+		assert!(History::decode(&data, "notes.txt", 4321).is_none());
+		assert!(History::decode(&data[..data.len() - 1], "notes.txt", 1234).is_none());
+	}
+
+	#[test]
+	fn store_and_load() {
+		let dir = std::env::temp_dir().join(format!("edit-rust-undo-test-{}", std::process::id()));
+		let mut rope = Rope::new();
+		rope.insert_bytes(b"one three", 0);
+		let hash = content_hash(&rope);
+
+		sample().store(&dir, "notes.txt", 3, hash).unwrap();
+		assert_eq!(History::load(&dir, "notes.txt", hash).map(|h| h.current()), Some(3));
+		//Only the history itself is left behind, and only its owner can read it.
+		assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+		assert_eq!(std::fs::metadata(undo_file(&dir, "notes.txt")).unwrap().permissions().mode() & 0o777, 0o600);
+
+		//A history for contents which have since changed is discarded.
+		rope.insert_bytes(b"!", 0);
+		assert!(History::load(&dir, "notes.txt", content_hash(&rope)).is_none());
+		assert!(History::load(&dir, "notes.txt", hash).is_none());
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -1,24 +1,30 @@
-use std::io::Read;
+use std::{io::{BufWriter, ErrorKind, Read, Write}, ops::ControlFlow, time::Duration, os::unix::fs::{fchown, MetadataExt}, path::{Path, PathBuf}};
 
-use crate::{send, terminil::{prelude::*, input::Input}};
+use crate::{send, terminil::{prelude::*, event_loop::{EventLoop, LoopEvent, TimerId}, input::Input}};
 
 mod rope;
 mod buffer;
+mod command;
+pub mod config;
+mod grapheme;
+mod history;
 pub mod style;
+mod watcher;
 
 use buffer::Buffer;
+use regex::bytes::Regex;
+use command::Command;
+use config::Config;
 use rope::Rope;
-use style::Stylesheet;
+use style::{StatusbarModule, Stylesheet};
+use watcher::FileWatcher;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub enum Mode {
+	#[default]
 	Normal,
 	Insert,
 	Select,
-} impl Default for Mode {
-	fn default() -> Self {
-		Mode::Normal
-	}
 }
 
 #[derive(Debug, Clone)]
@@ -47,67 +53,650 @@ pub struct File {
 
 		Ok(rope)
 	}
+
+	pub fn path(&self) -> &str {
+		&self.path
+	}
+	pub fn is_readonly(&self) -> bool {
+		self.is_readonly
+	}
+
+	///Atomically replaces the file's contents with `rope`.
+	///
+	///The data is streamed to a temporary file in the same directory, synced, and then renamed over the original,
+	///so a crash or full disk never leaves a half-written file behind. The original file's permissions and
+	///ownership are carried over to the new one.
+	pub fn write_rope(&self, rope: &Rope) -> Result<(), std::io::Error> {
+		if self.is_readonly {
+			return Err(std::io::Error::new(ErrorKind::PermissionDenied, "file was opened read-only"));
+		}
+
+		//Write through symlinks rather than replacing them.
+		let path = match std::fs::canonicalize(&self.path) {
+			Ok(p) => p,
+			Err(e) if e.kind() == ErrorKind::NotFound => PathBuf::from(&self.path),
+			Err(e) => return Err(e),
+		};
+		let original = match std::fs::metadata(&path) {
+			Ok(m) => Some(m),
+			Err(e) if e.kind() == ErrorKind::NotFound => None,
+			Err(e) => return Err(e),
+		};
+
+		let dir = match path.parent() {
+			Some(d) if !d.as_os_str().is_empty() => d,
+			_ => Path::new("."),
+		};
+		let file_name = path.file_name().ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "path does not name a file"))?;
+		let temp_path = dir.join(format!(".{}.{}.tmp", file_name.to_string_lossy(), std::process::id()));
+
+		let result = File::write_temp(&temp_path, rope, original.as_ref()).and_then(|_| std::fs::rename(&temp_path, &path));
+		if result.is_err() {
+			let _ = std::fs::remove_file(&temp_path);
+			return result;
+		}
+
+		//Make sure the rename itself survives a crash. The file has already been replaced by now, so failing to do so
+		//doesn't make the save fail.
+		let _ = std::fs::File::open(dir).and_then(|dir| dir.sync_all());
+
+		Ok(())
+	}
+
+	fn write_temp(temp_path: &Path, rope: &Rope, original: Option<&std::fs::Metadata>) -> Result<(), std::io::Error> {
+		let create = || std::fs::OpenOptions::new().write(true).create_new(true).open(temp_path);
+		let file = match create() {
+			//Left behind by a crash of an earlier process with the same ID, so nothing can still be writing to it.
+			Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+				std::fs::remove_file(temp_path)?;
+				create()?
+			},
+			result => result?,
+		};
+
+		if let Some(original) = original {
+			//Only privileged users can give files away, so failing to copy the owner isn't fatal. Changing the owner
+			//clears the setuid and setgid bits, so the permissions are copied afterwards.
+			let _ = fchown(&file, Some(original.uid()), Some(original.gid()));
+			file.set_permissions(original.permissions())?;
+		}
+
+		let mut writer = BufWriter::new(file);
+		for chunk in rope.chunks() {
+			writer.write_all(chunk)?;
+		}
+		writer.into_inner().map_err(|e| e.into_error())?.sync_all()
+	}
 }
 
 #[derive(Debug, Clone)]
 pub enum Source {
-	String(String),
+	///Data to edit in a scratch buffer, which has no file to be saved to. It's kept as bytes like files are.
+	Scratch(Vec<u8>),
 	File(File),
+	///Data piped in to be edited in filter mode. Saving only marks it as done, and the result is picked up with
+	///[Editor::filter_output] once the editor exits. It's kept as bytes, so that whatever isn't edited passes through
+	///untouched even if it isn't UTF-8.
+	Filter(Vec<u8>),
+}
+
+///How long messages stay on the bottom line if nothing else replaces them.
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+///How often open files are checked for changes made by other programs.
+const FILE_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+///Returns the editor's directory within an XDG base directory: `$var/edit-rust`, or `$HOME/fallback/edit-rust` if `var`
+///isn't set. As the specification says, empty and relative paths are ignored, in `$HOME` as well.
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+	let absolute = |name| std::env::var_os(name).map(PathBuf::from).filter(|dir| dir.is_absolute());
+	let base = absolute(var).or_else(|| Some(absolute("HOME")?.join(fallback)))?;
+
+	Some(base.join("edit-rust"))
+}
+
+///Work which finishes outside of the editor's own event handling, from timers and worker threads.
+#[derive(Debug)]
+enum Task {
+	ClearMessage,
+	///The file at this path was modified, possibly by the editor itself, and now has contents with this hash.
+	FileChanged(String, u64),
 }
 
 pub struct Editor {
-	input: Input,
+	///Where events come from while the editor is running interactively. Headless editors, used for batch mode, have
+	///no event loop and never touch the terminal.
+	events: Option<EventLoop<Task>>,
+	///Tells the editor about changes to its buffers' files. Like the event loop, only interactive editors have one.
+	watcher: Option<FileWatcher>,
 	stylesheet: Stylesheet,
+	config: Config,
+	screen: Screen,
 	buffers: Vec<Buffer>,
 	current_buffer: usize,
 	mode: Mode,
+	///The contents of the command prompt, if it is open.
+	command_line: Option<String>,
+	///A message to show the user on the bottom line, such as the result of a command.
+	message: Option<String>,
+	///The timer which clears the current message.
+	message_timer: Option<TimerId>,
+	///False only once the user has quit normally, rather than with `:q!` or by the terminal going away. Unsaved
+	///changes can't be left behind by a normal quit, so this tells filter mode whether to pass its buffer on.
+	discarded: bool,
+	///Set when the last command failed, rather than just leaving a message.
+	failed: bool,
 } impl Editor {
 	pub fn new() -> Self {
+		Editor::with_events(Some(EventLoop::new(Input::new()).expect("to be able to create the event loop")))
+	}
+	///Creates an editor which runs commands given with [Editor::run_command] instead of reading from the terminal.
+	pub fn headless() -> Self {
+		Editor::with_events(None)
+	}
+	fn with_events(events: Option<EventLoop<Task>>) -> Self {
+		let watcher = events.as_ref().map(|events| {
+			let waker = events.waker();
+			//Sending only fails once the editor has shut down.
+			FileWatcher::new(FILE_WATCH_INTERVAL, move |path, hash| waker.send(Task::FileChanged(path, hash)).is_ok())
+		});
+
 		Editor {
-			input: Input::new(),
+			events,
+			watcher,
 			buffers: Vec::new(),
 			current_buffer: 0,
 			mode: Mode::default(),
 			stylesheet: Stylesheet::default(),
+			config: Config::default(),
+			screen: Screen::default(),
+			command_line: None,
+			message: None,
+			message_timer: None,
+			discarded: true,
+			failed: false,
 		}
 	}
-	pub fn open(&mut self, source: Source) -> Result<(), std::io::Error> {
-		self.buffers.push(Buffer::new(source)?);
+	///Opens a new buffer, with the cursor at the zero-indexed `(line, col)` of `position` if it's given.
+	pub fn open(&mut self, source: Source, position: Option<(usize, usize)>) -> Result<(), std::io::Error> {
+		let mut buffer = Buffer::new(source)?;
+		if let Some((line, col)) = position {
+			buffer.jump_to(line, col);
+		}
+		self.buffers.push(buffer);
+		self.watch_files();
 		Ok(())
 	}
+	///Watches the files of every buffer for changes made by other programs.
+	fn watch_files(&self) {
+		if let Some(watcher) = &self.watcher {
+			watcher.set_paths(self.buffers.iter().filter_map(Buffer::file).map(File::path));
+		}
+	}
+	///Returns the contents of the [Source::Filter] buffer as it was last saved, or `None` if it was never saved or the
+	///editor was left without keeping it.
+	pub fn filter_output(&self) -> Option<&Rope> {
+		let buffer = self.buffers.iter().find(|b| matches!(b.source(), Source::Filter(_)))?;
+		(!self.discarded && buffer.filter_saved() && !buffer.is_modified()).then(|| buffer.rope())
+	}
+	///Shows `message` on the bottom line, as if it were the result of a command.
+	pub fn show_message(&mut self, message: String) {
+		self.message = Some(message);
+	}
 	pub fn set_style(&mut self, stylesheet: Stylesheet) {
 		self.stylesheet = stylesheet;
 	}
-	fn render(&self) {
-		print!("{}{}",Action::CursorVisible(false).to_ansi(),Motion::ScreenStart.to_ansi());
-		print!("{}{}",self.stylesheet.background.as_bg(), self.stylesheet.text.as_fg());
-		let (width, height) = self.input.dimensions();
-		for byte in &self.buffers[self.current_buffer] {
-			if byte == b'\n' {
-				print!("{}\n{}",Action::EraseToLineEnd.to_ansi(), Motion::LineStart.to_ansi());
-			} else {
-				print!("{}", char::from_u32(byte as u32).unwrap_or('@'));
+	pub fn set_config(&mut self, config: Config) {
+		self.config = config;
+	}
+	///Returns the event loop, which only interactive editors have.
+	fn events(&mut self) -> &mut EventLoop<Task> {
+		self.events.as_mut().expect("only interactive editors to be run")
+	}
+	///Shows `message` as the result of a command which failed.
+	fn fail(&mut self, message: String) {
+		self.message = Some(message);
+		self.failed = true;
+	}
+	///Runs a command as if it had been typed at the prompt. Failures are returned as errors; other messages are
+	///dropped, as they only describe what happened.
+	pub fn run_command(&mut self, command: &str) -> Result<ControlFlow<()>, String> {
+		self.failed = false;
+		self.message = None;
+		let flow = self.execute(Command::parse(command)?);
+
+		match self.message.take() {
+			Some(message) if self.failed => Err(message),
+			_ => Ok(flow),
+		}
+	}
+	///Returns the `(width, height)` of the area buffers are shown in, which leaves room for the statusbar and prompt.
+	fn text_area(&self) -> (usize, usize) {
+		let (width, height) = self.events.as_ref().map_or(self.screen.size(), |events| events.input().dimensions());
+		(width as usize, height.saturating_sub(2) as usize)
+	}
+	fn scroll_to_cursor(&mut self) {
+		let (width, height) = self.text_area();
+		self.buffers[self.current_buffer].scroll_to_cursor(width, height, &self.config);
+	}
+	///Returns the style used for buffer text and the prompt.
+	fn text_style(&self) -> Style {
+		Style::fg(self.stylesheet.text).inherit(Style::bg(self.stylesheet.background))
+	}
+	///Draws the whole editor into `screen`.
+	fn draw(&self, screen: &mut Screen) {
+		let (width, height) = screen.size();
+		let text_style = self.text_style();
+		screen.clear(text_style);
+
+		let selected_style = Style::bg(self.stylesheet.selection).inherit(text_style);
+
+		let buffer = &self.buffers[self.current_buffer];
+		let rope = buffer.rope();
+		let view = buffer.viewport();
+		let selections = buffer.selection_ranges();
+		for row in 0..height.saturating_sub(2) {
+			let line = view.top_line + row as usize;
+			if line >= rope.line_count() {
+				break;
+			}
+
+			let mut col = 0;
+			let mut offset = rope.line_to_byte(line);
+			for cluster in rope.line(line).graphemes().filter(|c| !c.ends_with(b"\n")) {
+				if col >= view.left_col + width as usize {
+					break;
+				}
+				let next = grapheme::next_col(col, &cluster);
+				let style = if selections.iter().any(|s| s.contains(&offset)) { selected_style } else { text_style };
+				if *cluster == *b"\t" {
+					//Tabs are blank up to the next tab stop, and can be cut off by either edge.
+					for x in col.max(view.left_col)..next.min(view.left_col + width as usize) {
+						screen.put((x - view.left_col) as u16, row, " ", style);
+					}
+				} else if col >= view.left_col {
+					//Wide characters cut off by the left edge are left out entirely.
+					screen.put((col - view.left_col) as u16, row, &String::from_utf8_lossy(&cluster), style);
+				}
+				col = next;
+				offset += cluster.len();
 			}
 		}
-		print!("{}{}", Motion::ToPosition(0, height-2).to_ansi(), self.stylesheet.statusbar.render(self));
-		send!();
+
+		if height >= 2 {
+			self.stylesheet.statusbar.render(self, screen, height - 2);
+		}
+
+		let prompt_line = height.saturating_sub(1);
+		if let Some(command) = &self.command_line {
+			let len = screen.print(0, prompt_line, &format!(":{command}"), text_style);
+			screen.set_cursor(Some((len, prompt_line)));
+		} else {
+			screen.print(0, prompt_line, self.message.as_deref().unwrap_or_default(), text_style);
+
+			let (line, _) = buffer.cursor();
+			let row = line.checked_sub(view.top_line).filter(|row| *row < height.saturating_sub(2) as usize);
+			let col = buffer.cursor_display_col().checked_sub(view.left_col).filter(|col| *col < width as usize);
+			screen.set_cursor(row.zip(col).map(|(row, col)| (col as u16, row as u16)));
+		}
+	}
+	///Draws the editor and sends the changes to the terminal.
+	fn render(&mut self) {
+		let (width, height) = self.events().input().dimensions();
+		let mut screen = std::mem::take(&mut self.screen);
+		if screen.size() != (width, height) {
+			screen.resize(width, height);
+		}
+
+		self.draw(&mut screen);
+		screen.present();
+		self.screen = screen;
 	}
 	///Starts the main loop of the editor.
 	pub fn run(&mut self) {
+		//Messages can be left from before the editor started, such as files which failed to open.
+		if self.message.is_some() {
+			self.message_timer = Some(self.events().set_timer(MESSAGE_TIMEOUT, Task::ClearMessage));
+		}
+		self.scroll_to_cursor();
 		self.render();
-		'main: loop {
-			if let Some(ev) = self.input.get_event() {
-				use crate::terminil::input::{Event, SpecialKey};
-				match ev {
-					Event::SpecialKey(SpecialKey::Escape) => break 'main,
-					e => self.buffers[self.current_buffer].trigger_event(e, &self.mode),
+		while let Some(event) = self.events().next() {
+			let message = self.message.clone();
+			let flow = match event {
+				LoopEvent::Input(event) => self.handle_event(event),
+				LoopEvent::Timer(task) | LoopEvent::Message(task) => self.handle_task(task),
+			};
+			if flow.is_break() {
+				break;
+			}
+
+			if self.message.is_some() && self.message != message {
+				if let Some(timer) = self.message_timer.take() {
+					self.events().cancel_timer(timer);
 				}
+				self.message_timer = Some(self.events().set_timer(MESSAGE_TIMEOUT, Task::ClearMessage));
 			}
+			self.scroll_to_cursor();
 			self.render();
 		}
+
+		for buffer in &self.buffers {
+			let _ = buffer.store_history();
+		}
+	}
+	fn handle_event(&mut self, event: Event) -> ControlFlow<()> {
+		match &event {
+			//The terminal may have rewrapped or cleared what was on screen, so everything has to be drawn again.
+			Event::Resize(..) => {
+				self.screen.invalidate();
+				return ControlFlow::Continue(());
+			},
+			//Key releases are only reported by some terminals, so nothing is bound to them. They (and the other events
+			//the user didn't deliberately send) mustn't clear the message either, or letting go of Ctrl-S would hide it.
+			Event::Key(KeyEvent { kind: KeyKind::Release, .. })
+			| Event::Mouse(MouseEvent { kind: MouseKind::Release(_) | MouseKind::Move, .. })
+			| Event::Focus(_) | Event::Reply(_) | Event::Unknown => return ControlFlow::Continue(()),
+			_ => {},
+		}
+
+		self.message = None;
+		let key = match &event {
+			Event::Key(key) => Some((key.key, key.modifiers)),
+			_ => None,
+		};
+
+		if let Some(command) = &mut self.command_line {
+			match (key, event) {
+				(Some((Key::Special(SpecialKey::Escape), Modifiers::NONE)), _) => self.command_line = None,
+				(Some((Key::Special(SpecialKey::Backspace), Modifiers::NONE)), _) if command.pop().is_none() => self.command_line = None,
+				(Some((Key::Char('\n'), Modifiers::NONE)), _) => {
+					let command = self.command_line.take().unwrap_or_default();
+					match Command::parse(&command) {
+						Ok(command) => return self.execute(command),
+						Err(e) => self.message = Some(e),
+					}
+				},
+				(Some((Key::Char(c), Modifiers::NONE)), _) => command.push(c),
+				(_, Event::Paste(data)) => command.push_str(&String::from_utf8_lossy(&data)),
+				_ => {},
+			}
+			return ControlFlow::Continue(());
+		}
+
+		if let Event::Mouse(mouse) = event {
+			return self.handle_mouse(mouse);
+		}
+
+		match (self.mode, key) {
+			(_, Some((Key::Char('s'), Modifiers::CTRL))) => return self.execute(Command::Write(None)),
+			(_, Some((Key::Char('z'), Modifiers::CTRL))) => return self.execute(Command::Suspend),
+			(Mode::Normal, Some((Key::Special(SpecialKey::Escape), Modifiers::NONE))) => return self.execute(Command::Quit { force: false }),
+			(Mode::Normal, Some((Key::Char('i'), Modifiers::NONE))) => self.mode = Mode::Insert,
+			(Mode::Normal, Some((Key::Char(':'), Modifiers::NONE))) => self.command_line = Some(String::new()),
+			(Mode::Normal, Some((Key::Char('u'), Modifiers::NONE))) => return self.execute(Command::Undo),
+			(Mode::Normal, Some((Key::Char('r'), Modifiers::CTRL))) => return self.execute(Command::Redo),
+			(Mode::Insert, Some((Key::Special(SpecialKey::Escape), Modifiers::NONE))) => self.mode = Mode::Normal,
+			(mode, _) => self.buffers[self.current_buffer].trigger_event(event, &mode),
+		}
+
+		ControlFlow::Continue(())
+	}
+	fn handle_task(&mut self, task: Task) -> ControlFlow<()> {
+		match task {
+			Task::ClearMessage => {
+				self.message = None;
+				self.message_timer = None;
+			},
+			Task::FileChanged(path, hash) => {
+				let changed = self.buffers.iter()
+					.filter(|b| b.file().is_some_and(|f| f.path() == path))
+					.any(|b| b.saved_hash() != hash);
+				if changed {
+					self.message = Some(format!("{path} was changed by another program"));
+				}
+			},
+		}
+
+		ControlFlow::Continue(())
+	}
+	fn handle_mouse(&mut self, mouse: MouseEvent) -> ControlFlow<()> {
+		let (width, height) = self.text_area();
+		let (x, y) = (mouse.x as usize, mouse.y as usize);
+		let lines = self.config.wheel_scroll_lines as isize;
+		let buffer = &mut self.buffers[self.current_buffer];
+
+		match mouse.kind {
+			MouseKind::ScrollUp => buffer.scroll_by(-lines, height, &self.config),
+			MouseKind::ScrollDown => buffer.scroll_by(lines, height, &self.config),
+			MouseKind::Press(MouseButton::Left) if y < height => buffer.click(x, y),
+			MouseKind::Press(MouseButton::Left) if y == height => {
+				let module = self.stylesheet.statusbar.module_at(self, width as u16, mouse.x);
+				match module {
+					Some(StatusbarModule::Mode) => self.mode = match self.mode {
+						Mode::Insert => Mode::Normal,
+						_ => Mode::Insert,
+					},
+					Some(StatusbarModule::Modified) => return self.execute(Command::Write(None)),
+					Some(StatusbarModule::Filename) => {
+						let buffer = &self.buffers[self.current_buffer];
+						self.message = Some(format!("{}: {} lines, {} bytes", buffer.name(), buffer.rope().line_count(), buffer.rope().len()));
+					},
+					_ => {},
+				}
+			},
+			//Dragging past the bottom of the text keeps selecting along the last line on screen.
+			MouseKind::Drag(MouseButton::Left) => buffer.drag_to(x, y.min(height.saturating_sub(1))),
+			_ => {},
+		}
+
+		ControlFlow::Continue(())
+	}
+	fn execute(&mut self, command: Command) -> ControlFlow<()> {
+		match command {
+			Command::Write(path) => {
+				let buffer = &mut self.buffers[self.current_buffer];
+				let target = path.clone().unwrap_or_else(|| buffer.name().to_string());
+				let result = match &path {
+					Some(path) => buffer.save_as(path),
+					None => buffer.save(),
+				};
+				match result {
+					Ok(()) => self.message = Some(format!("Wrote {} bytes to {target}", buffer.rope().len())),
+					Err(e) => self.fail(format!("Could not save {target}: {e}")),
+				}
+				//The buffer may have a new file to watch.
+				if path.is_some() {
+					self.watch_files();
+				}
+			},
+			Command::Quit { force } => {
+				let unsaved = self.buffers.iter().filter(|b| b.is_modified()).count();
+				if force || unsaved == 0 {
+					self.discarded = force;
+					return ControlFlow::Break(());
+				}
+				self.fail(format!("{unsaved} buffer(s) have unsaved changes (use :q! to quit anyway)"));
+			},
+			Command::Undo => if !self.buffers[self.current_buffer].undo() {
+				self.message = Some("Already at oldest change".to_string());
+			},
+			Command::Redo => if !self.buffers[self.current_buffer].redo() {
+				self.message = Some("Already at newest change".to_string());
+			},
+			Command::Earlier(steps) => {
+				let moved = self.buffers[self.current_buffer].time_travel(-(steps as isize));
+				self.message = Some(format!("Went back {moved} change(s)"));
+			},
+			Command::Later(steps) => {
+				let moved = self.buffers[self.current_buffer].time_travel(steps as isize);
+				self.message = Some(format!("Went forward {moved} change(s)"));
+			},
+			Command::Suspend => match &mut self.events {
+				Some(events) => {
+					events.input_mut().suspend();
+					//The terminal was used by something else in the meantime, and may have been resized.
+					self.screen.invalidate();
+				},
+				None => self.fail("There is no terminal to suspend in batch mode".to_string()),
+			},
+			Command::SwitchBuffer(steps) => {
+				let count = self.buffers.len() as isize;
+				self.current_buffer = (self.current_buffer as isize + steps).rem_euclid(count) as usize;
+				self.message = Some(format!("Buffer {} of {count}: {}", self.current_buffer + 1, self.buffers[self.current_buffer].name()));
+			},
+			Command::Goto(line) => self.buffers[self.current_buffer].jump_to(line, 0),
+			Command::Search(pattern) => match Regex::new(&pattern) {
+				Ok(regex) => if !self.buffers[self.current_buffer].search(&regex) {
+					self.fail(format!("Pattern not found: {pattern}"));
+				},
+				Err(e) => self.fail(format!("Invalid pattern: {e}")),
+			},
+			Command::Substitute { pattern, replacement, global } => match Regex::new(&pattern) {
+				Ok(regex) => {
+					let count = self.buffers[self.current_buffer].substitute(&regex, replacement.as_bytes(), global);
+					self.message = Some(format!("Replaced {count} match(es)"));
+				},
+				Err(e) => self.fail(format!("Invalid pattern: {e}")),
+			},
+			Command::Insert(text) => self.buffers[self.current_buffer].insert(text.as_bytes()),
+			Command::DeleteLines(count) => self.buffers[self.current_buffer].delete_lines(count),
+			Command::Print => {
+				if self.events.is_some() {
+					self.fail("Printing is only possible in batch mode".to_string());
+					return ControlFlow::Continue(());
+				}
+				let mut stdout = std::io::stdout().lock();
+				let result = self.buffers[self.current_buffer].rope().chunks().try_for_each(|chunk| stdout.write_all(chunk));
+				if let Err(e) = result.and_then(|()| stdout.flush()) {
+					self.fail(format!("Could not print: {e}"));
+				}
+			},
+			Command::WriteQuit => {
+				let _ = self.execute(Command::Write(None));
+				//Leave the error from the failed save on screen rather than replacing it.
+				if !self.buffers[self.current_buffer].is_modified() {
+					return self.execute(Command::Quit { force: false });
+				}
+			},
+		}
+
+		ControlFlow::Continue(())
 	}
 } impl Drop for Editor {
 	fn drop(&mut self) {
-		print!("{}",Action::CursorVisible(true).to_ansi());
+		if self.events.is_some() {
+			send!("{}", Action::CursorVisible(true).to_ansi());
+		}
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+	use std::{ops::ControlFlow, os::unix::fs::PermissionsExt, path::PathBuf};
+
+	use super::{Editor, File, Rope, Source};
+	use crate::terminil::prelude::*;
+
+	///Creates an empty directory for a test to write files in. Each test has its own, so they can run in parallel.
+	fn temp_dir(test: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("edit-rust-{test}-test-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	#[test]
+	fn write_rope() {
+		let dir = temp_dir("write-rope");
+		let path = dir.join("file.txt").to_string_lossy().to_string();
+		std::fs::write(&path, "old contents").unwrap();
+		std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o2750)).unwrap();
+		//A temp file left behind by a crash mustn't stop the file being saved.
+		let stale = dir.join(format!(".file.txt.{}.tmp", std::process::id()));
+		std::fs::write(&stale, "stale").unwrap();
+
+		let mut rope = Rope::new();
+		rope.insert_bytes(b"new contents", 0);
+		File::open(&path, false).write_rope(&rope).unwrap();
+
+		assert_eq!(std::fs::read(&path).unwrap(), b"new contents");
+		assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o7777, 0o2750);
+		assert!(!stale.exists());
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn refuse_readonly() {
+		let dir = temp_dir("refuse-readonly");
+		let path = dir.join("file.txt").to_string_lossy().to_string();
+		std::fs::write(&path, "old contents").unwrap();
+
+		let mut rope = Rope::new();
+		rope.insert_bytes(b"new contents", 0);
+		assert!(File::open(&path, true).write_rope(&rope).is_err());
+		assert_eq!(std::fs::read(&path).unwrap(), b"old contents");
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn headless_commands() {
+		let mut editor = Editor::headless();
+		editor.open(Source::Scratch(b"a = 1\nb = a\n".to_vec()), None).unwrap();
+
+		assert_eq!(editor.run_command("s/a/x/g"), Ok(ControlFlow::Continue(())));
+		assert_eq!(editor.run_command("2"), Ok(ControlFlow::Continue(())));
+		assert_eq!(editor.run_command("insert y"), Ok(ControlFlow::Continue(())));
+		assert_eq!(editor.buffers[0].rope().clone().collapse(), b"x = 1\nyb = x\n");
+
+		assert!(editor.run_command("/z").is_err());
+		assert!(editor.run_command("frobnicate").is_err());
+		//Unsaved changes stop a normal quit.
+		assert!(editor.run_command("q").is_err());
+		assert_eq!(editor.run_command("q!"), Ok(ControlFlow::Break(())));
+	}
+
+	#[test]
+	fn releases_keep_message() {
+		let mut editor = Editor::headless();
+		editor.open(Source::Scratch(b"text".to_vec()), None).unwrap();
+
+		let u = KeyEvent::from('u');
+		let _ = editor.handle_event(Event::Key(u));
+		assert_eq!(editor.message.as_deref(), Some("Already at oldest change"));
+		let _ = editor.handle_event(Event::Key(KeyEvent { kind: KeyKind::Release, ..u }));
+		let _ = editor.handle_event(Event::Focus(false));
+		assert_eq!(editor.message.as_deref(), Some("Already at oldest change"));
+
+		let _ = editor.handle_event(Event::Key(SpecialKey::Right.into()));
+		assert_eq!(editor.message, None);
+	}
+
+	#[test]
+	fn filter_output() {
+		let filter = |input: &[u8], commands: &[&str]| {
+			let mut editor = Editor::headless();
+			editor.open(Source::Filter(input.to_vec()), None).unwrap();
+			for command in commands {
+				let _ = editor.run_command(command);
+			}
+			editor.filter_output().map(|rope| rope.clone().collapse())
+		};
+		let quit = |commands: &[&str]| filter(b"text\n", commands);
+
+		assert_eq!(quit(&["q"]), None);
+		assert_eq!(quit(&["q!"]), None);
+		assert_eq!(quit(&["insert x", "q!"]), None);
+		assert_eq!(quit(&["wq"]), Some(b"text\n".to_vec()));
+		assert_eq!(quit(&["insert x", "w", "q"]), Some(b"xtext\n".to_vec()));
+		assert_eq!(quit(&["w", "insert x", "q!"]), None);
+		//Saving under another name is refused, and leaves the buffer to be passed on as usual.
+		assert_eq!(quit(&["insert x", "w filter_output.txt", "q!"]), None);
+		assert_eq!(quit(&["w filter_output.txt", "wq"]), Some(b"text\n".to_vec()));
+		assert!(!std::path::Path::new("filter_output.txt").exists());
+
+		//Input which isn't UTF-8 (Latin-1 here) comes back out exactly as it went in.
+		let latin1 = b"caf\xe9\r\n\xff\xfe\x80 diff\n";
+		assert_eq!(filter(latin1, &["wq"]), Some(latin1.to_vec()));
+	}
+}
@@ -1,14 +1,50 @@
-use std::mem;
+use std::{borrow::Cow, iter::{Copied, Flatten, Peekable}, mem, ops::Range};
+
+use super::grapheme::{self, count_chars, is_char_start};
+
+///Leaves are never grown past this many bytes; larger inserts are spread across several leaves.
+const MAX_LEAF_SIZE: usize = 1024;
+///Leaves smaller than this are merged with a neighbour instead of being edited in place.
+const MIN_LEAF_SIZE: usize = MAX_LEAF_SIZE / 4;
 
 #[derive(Debug, Clone)]
 struct RopeTrunkNode {
 	child0: Box<RopeNode>,
 	child1: Box<RopeNode>,
 	length: usize,
+	///Number of `\n` bytes within this subtree.
+	newlines: usize,
+	///Number of UTF-8 characters which start within this subtree.
+	chars: usize,
+	///Length of the longest path from this node to a leaf, counting both ends.
+	height: usize,
 } impl RopeTrunkNode {
+	///Creates a trunk node over two children, computing its metadata from them.
+	pub fn new(child0: RopeNode, child1: RopeNode) -> Self {
+		let mut trunk = RopeTrunkNode {
+			child0: Box::new(child0),
+			child1: Box::new(child1),
+			length: 0,
+			newlines: 0,
+			chars: 0,
+			height: 0,
+		};
+		trunk.refresh();
+
+		trunk
+	}
+
+	///Recomputes this node's metadata after one of its children has changed.
+	fn refresh(&mut self) {
+		self.length = self.child0.len() + self.child1.len();
+		self.newlines = self.child0.newlines() + self.child1.newlines();
+		self.chars = self.child0.chars() + self.child1.chars();
+		self.height = self.child0.height().max(self.child1.height()) + 1;
+	}
+
 	///Converts a Rope subtree into a single Leaf node.
 	pub fn collapse(self) -> RopeLeafNode {
-		RopeLeafNode(self.collapse_bytes())
+		RopeLeafNode::new(self.collapse_bytes())
 	}
 
 	///Converts a Rope subtree into a flattened version
@@ -21,37 +57,72 @@ struct RopeTrunkNode {
 }
 
 #[derive(Debug, Clone)]
-struct RopeLeafNode(Vec<u8>);
+struct RopeLeafNode {
+	bytes: Vec<u8>,
+	///Number of `\n` bytes within this leaf.
+	newlines: usize,
+	///Number of UTF-8 characters which start within this leaf.
+	chars: usize,
+}
 impl RopeLeafNode {
-	///Splits a leaf node into a trunk node at the given index. `i` is the index of the *first character* in the *second node*.
-	pub fn split(self, i: usize) -> RopeTrunkNode {
-		let original_length = self.0.len();
-		let (left, right) = self.0.split_at(i);
-
-		RopeTrunkNode {
-			child0: Box::new(RopeNode::Leaf(RopeLeafNode(left.to_owned()))),
-			child1: Box::new(RopeNode::Leaf(RopeLeafNode(right.to_owned()))),
-			length: original_length,
+	pub fn new(bytes: Vec<u8>) -> Self {
+		RopeLeafNode {
+			newlines: count_newlines(&bytes),
+			chars: count_chars(&bytes),
+			bytes,
 		}
 	}
+	pub fn len(&self) -> usize {
+		self.bytes.len()
+	}
+	///Inserts `bytes` before index `i`.
+	pub fn insert(&mut self, bytes: &[u8], i: usize) {
+		self.newlines += count_newlines(bytes);
+		self.chars += count_chars(bytes);
+		self.bytes.splice(i..i, bytes.iter().copied());
+	}
+	///Removes the bytes within `range`.
+	pub fn delete(&mut self, range: Range<usize>) {
+		self.newlines -= count_newlines(&self.bytes[range.clone()]);
+		self.chars -= count_chars(&self.bytes[range.clone()]);
+		self.bytes.drain(range);
+	}
+	///Splits the leaf in two, returning everything from `i` onward.
+	pub fn split_off(&mut self, i: usize) -> RopeLeafNode {
+		let right = RopeLeafNode::new(self.bytes.split_off(i));
+		self.newlines -= right.newlines;
+		self.chars -= right.chars;
+
+		right
+	}
 	///Appends another leaf node to this one.
 	pub fn append(&mut self, mut other: RopeLeafNode) {
-		self.0.append(&mut other.0);
+		self.newlines += other.newlines;
+		self.chars += other.chars;
+		self.bytes.append(&mut other.bytes);
 	}
 }
 
-impl Into<RopeLeafNode> for RopeTrunkNode {
-	fn into(self) -> RopeLeafNode {
-		self.collapse()
+fn count_newlines(bytes: &[u8]) -> usize {
+	bytes.iter().filter(|b| **b == b'\n').count()
+}
+
+impl From<RopeTrunkNode> for RopeLeafNode {
+	fn from(value: RopeTrunkNode) -> Self {
+		value.collapse()
 	}
 }
 
-impl Into<Vec<u8>> for RopeTrunkNode {
-	fn into(self) -> Vec<u8> {
-		self.collapse_bytes()
+impl From<RopeTrunkNode> for Vec<u8> {
+	fn from(value: RopeTrunkNode) -> Self {
+		value.collapse_bytes()
 	}
 }
 
+///A node within a [Rope].
+///
+///Trunks are kept height-balanced (AVL-style): the heights of a trunk's children never differ by more than one,
+///so indexing, insertion and deletion are all O(log n). Trunks never have a `RopeNode::None` child.
 #[derive(Debug, Clone)]
 enum RopeNode {
 	None,
@@ -60,14 +131,35 @@ enum RopeNode {
 } impl RopeNode {
 	pub fn len(&self) -> usize {
 		match self {
-			RopeNode::Leaf(s) => s.0.len(),
+			RopeNode::Leaf(s) => s.len(),
 			RopeNode::Trunk(t) => t.length,
 			RopeNode::None => 0,
 		}
 	}
+	pub fn newlines(&self) -> usize {
+		match self {
+			RopeNode::Leaf(l) => l.newlines,
+			RopeNode::Trunk(t) => t.newlines,
+			RopeNode::None => 0,
+		}
+	}
+	pub fn chars(&self) -> usize {
+		match self {
+			RopeNode::Leaf(l) => l.chars,
+			RopeNode::Trunk(t) => t.chars,
+			RopeNode::None => 0,
+		}
+	}
+	pub fn height(&self) -> usize {
+		match self {
+			RopeNode::Leaf(_) => 1,
+			RopeNode::Trunk(t) => t.height,
+			RopeNode::None => 0,
+		}
+	}
 	pub fn byte_at(&self, i: usize) -> u8 {
 		match self {
-			RopeNode::Leaf(l) => l.0[i],
+			RopeNode::Leaf(l) => l.bytes[i],
 			RopeNode::Trunk(t) => if i < t.child0.len() {
 					t.child0.byte_at(i)
 				} else {
@@ -76,6 +168,100 @@ enum RopeNode {
 			RopeNode::None => 0,
 		}
 	}
+
+	///Returns the index of the `n`th (zero-indexed) newline within this subtree.
+	pub fn newline_index(&self, n: usize) -> usize {
+		match self {
+			RopeNode::Leaf(l) => l.bytes.iter()
+				.enumerate()
+				.filter(|(_, b)| **b == b'\n')
+				.nth(n)
+				.expect("the leaf to contain the requested newline").0,
+			RopeNode::Trunk(t) => if n < t.child0.newlines() {
+					t.child0.newline_index(n)
+				} else {
+					t.child0.len() + t.child1.newline_index(n - t.child0.newlines())
+				},
+			RopeNode::None => panic!("RopeNode::None contains no newlines"),
+		}
+	}
+
+	///Returns the number of newlines within `[0, i)`.
+	pub fn newlines_before(&self, i: usize) -> usize {
+		match self {
+			RopeNode::Leaf(l) => count_newlines(&l.bytes[..i]),
+			RopeNode::Trunk(t) => if i <= t.child0.len() {
+					t.child0.newlines_before(i)
+				} else {
+					t.child0.newlines() + t.child1.newlines_before(i - t.child0.len())
+				},
+			RopeNode::None => 0,
+		}
+	}
+
+	///Returns the index of the first byte of the `n`th (zero-indexed) character, or `len()` if `n` is the character count.
+	pub fn char_index(&self, n: usize) -> usize {
+		match self {
+			RopeNode::Leaf(l) => l.bytes.iter()
+				.enumerate()
+				.filter(|(_, b)| is_char_start(**b))
+				.nth(n)
+				.map_or(l.len(), |(i, _)| i),
+			RopeNode::Trunk(t) => if n < t.child0.chars() {
+					t.child0.char_index(n)
+				} else {
+					t.child0.len() + t.child1.char_index(n - t.child0.chars())
+				},
+			RopeNode::None => 0,
+		}
+	}
+
+	///Returns the number of characters which start within `[0, i)`.
+	pub fn chars_before(&self, i: usize) -> usize {
+		match self {
+			RopeNode::Leaf(l) => count_chars(&l.bytes[..i]),
+			RopeNode::Trunk(t) => if i <= t.child0.len() {
+					t.child0.chars_before(i)
+				} else {
+					t.child0.chars() + t.child1.chars_before(i - t.child0.len())
+				},
+			RopeNode::None => 0,
+		}
+	}
+
+	///Returns the contents of the leaf containing byte `i`, along with the index of the leaf's first byte.
+	pub fn leaf_at(&self, i: usize) -> (&[u8], usize) {
+		match self {
+			RopeNode::Leaf(l) => (&l.bytes, 0),
+			RopeNode::Trunk(t) => if i < t.child0.len() {
+					t.child0.leaf_at(i)
+				} else {
+					let (leaf, start) = t.child1.leaf_at(i - t.child0.len());
+					(leaf, start + t.child0.len())
+				},
+			RopeNode::None => (&[], 0),
+		}
+	}
+
+	///Builds a balanced subtree holding `bytes`, split into leaves of at most `MAX_LEAF_SIZE` bytes.
+	pub fn from_bytes(bytes: &[u8]) -> RopeNode {
+		if bytes.is_empty() {
+			RopeNode::None
+		} else if bytes.len() <= MAX_LEAF_SIZE {
+			RopeNode::Leaf(RopeLeafNode::new(bytes.to_vec()))
+		} else {
+			//Avoid splitting a multi-byte character between two leaves where possible.
+			let mut mid = bytes.len() / 2;
+			while mid > bytes.len() / 2 - 3 && !is_char_start(bytes[mid]) {
+				mid -= 1;
+			}
+			if !is_char_start(bytes[mid]) {
+				mid = bytes.len() / 2;
+			}
+			let (left, right) = bytes.split_at(mid);
+			RopeNode::Trunk(RopeTrunkNode::new(RopeNode::from_bytes(left), RopeNode::from_bytes(right)))
+		}
+	}
 	
 	///Replaces `self` with a RopeNode::None and returns the original value.
 	fn take(&mut self) -> RopeNode {
@@ -83,109 +269,216 @@ enum RopeNode {
 	}
 
 	pub fn insert_byte(&mut self, byte: u8, i: usize) {
+		self.insert_bytes(&[byte], i);
+	}
+
+	pub fn insert_bytes(&mut self, bytes: &[u8], i: usize) {
+		if i > self.len() {
+			panic!("Cannot insert a byte past the end of a RopeNode of length {}", self.len());
+		}
+		if bytes.is_empty() {
+			return;
+		}
+
+		//Most edits are small and land in a leaf with room to spare, which doesn't change the shape of the tree.
+		if !self.insert_in_leaf(bytes, i) {
+			let right = self.split_off(i);
+			self.append(RopeNode::from_bytes(bytes));
+			self.append(right);
+		}
+	}
+
+	///Inserts `bytes` directly into the leaf containing `i` if it has room. Returns false (without modifying anything) otherwise.
+	fn insert_in_leaf(&mut self, bytes: &[u8], i: usize) -> bool {
 		match self {
 			RopeNode::Leaf(l) => {
-				if i == l.0.len() {
-					l.0.push(byte);
+				if l.len() + bytes.len() <= MAX_LEAF_SIZE {
+					l.insert(bytes, i);
+					true
 				} else {
-					//Temporarily replaces self with a RopeNode::None so that we can mutate what enum branch `self` falls under.
-					let inner = self.take();
-					if let RopeNode::Leaf(l) = inner {
-						let mut new_trunk ;
-
-						if i != 0 {
-							new_trunk = l.split(i);
-						} else {
-							new_trunk = RopeTrunkNode {
-								length: l.0.len(),
-								child0: Box::new(RopeNode::None),
-								child1: Box::new(RopeNode::Leaf(l)),
-							}
-						}
-
-						new_trunk.child0.insert_byte(byte, i);
-						*self = RopeNode::Trunk(new_trunk);
-					} else {
-						unreachable!()
-					}
+					false
 				}
 			},
 			RopeNode::Trunk(t) => {
-				t.length += 1;
-				if i < t.child0.len() {
-					t.child0.insert_byte(byte, i);
+				let inserted = if i < t.child0.len() {
+					t.child0.insert_in_leaf(bytes, i)
 				} else {
-					t.child1.insert_byte(byte, i - t.child0.len());
+					t.child1.insert_in_leaf(bytes, i - t.child0.len())
+				};
+				if inserted {
+					t.refresh();
 				}
+
+				inserted
 			},
-			RopeNode::None => {
-				if i == 0 {
-					*self = RopeNode::Leaf(RopeLeafNode(vec![byte]));
-				} else {
-					panic!("Cannot insert a byte past the end of RopeNode::None");
-				}
-			}
+			RopeNode::None => false,
 		}
 	}
 
-	pub fn insert_bytes(&mut self, bytes: &[u8], i: usize) {
+	///Removes the bytes within `range`, pruning any nodes which become empty.
+	pub fn delete_range(&mut self, range: Range<usize>) {
+		if range.start > range.end || range.end > self.len() {
+			panic!("Cannot delete {range:?} from a RopeNode of length {}", self.len());
+		}
+		if range.is_empty() {
+			return;
+		}
+
+		if !self.delete_in_leaf(range.clone()) {
+			let right = self.split_off(range.end);
+			self.split_off(range.start);
+			self.append(right);
+		}
+	}
+
+	///Removes `range` directly from a leaf if it lies within one and the leaf won't become too small.
+	///Returns false (without modifying anything) otherwise.
+	fn delete_in_leaf(&mut self, range: Range<usize>) -> bool {
 		match self {
 			RopeNode::Leaf(l) => {
-				if i == l.0.len() {
-					l.0.extend(bytes);
+				if l.len() - range.len() >= MIN_LEAF_SIZE {
+					l.delete(range);
+					true
 				} else {
-					//Temporarily replaces self with a RopeNode::None so that we can mutate whatl enum branch `self` falls under.
-					let inner = self.take();
-					if let RopeNode::Leaf(l) = inner {
-						let mut new_trunk ;
-
-						if i != 0 {
-							new_trunk = l.split(i);
-						} else {
-							new_trunk = RopeTrunkNode {
-								length: l.0.len(),
-								child0: Box::new(RopeNode::None),
-								child1: Box::new(RopeNode::Leaf(l)),
-							}
-						}
-
-						new_trunk.child0.insert_bytes(bytes, i);
-						*self = RopeNode::Trunk(new_trunk);
-					} else {
-						unreachable!()
-					}
+					false
 				}
 			},
 			RopeNode::Trunk(t) => {
-				t.length += bytes.len();
-				if i < t.child0.len() {
-					t.child0.insert_bytes(bytes, i);
+				let left_len = t.child0.len();
+				let deleted = if range.end <= left_len {
+					t.child0.delete_in_leaf(range.clone())
+				} else if range.start >= left_len {
+					t.child1.delete_in_leaf(range.start - left_len..range.end - left_len)
 				} else {
-					t.child1.insert_bytes(bytes, i - t.child0.len());
+					false
+				};
+				if deleted {
+					t.refresh();
 				}
-			}
-			RopeNode::None => {
-				if i == 0 {
-					*self = RopeNode::Leaf(RopeLeafNode(Vec::from(bytes)));
+
+				deleted
+			},
+			RopeNode::None => false,
+		}
+	}
+
+	///Replaces the bytes within `range` with `bytes`.
+	pub fn replace_range(&mut self, range: Range<usize>, bytes: &[u8]) {
+		let start = range.start;
+		self.delete_range(range);
+		self.insert_bytes(bytes, start);
+	}
+
+	///Splits the node in two at the given index. `self` keeps `[0, i)` and the returned node contains `[i, len)`.
+	pub fn split_off(&mut self, i: usize) -> RopeNode {
+		if i > self.len() {
+			panic!("Cannot split a RopeNode of length {} at {i}", self.len());
+		}
+
+		match self.take() {
+			RopeNode::Leaf(mut l) => {
+				let right = l.split_off(i);
+				if l.len() != 0 {
+					*self = RopeNode::Leaf(l);
+				}
+
+				if right.len() != 0 {
+					RopeNode::Leaf(right)
+				} else {
+					RopeNode::None
+				}
+			},
+			RopeNode::Trunk(RopeTrunkNode { mut child0, mut child1, .. }) => {
+				let left_len = child0.len();
+				if i <= left_len {
+					let right = child0.split_off(i);
+					*self = *child0;
+
+					RopeNode::join(right, *child1)
+				} else {
+					let right = child1.split_off(i - left_len);
+					*self = RopeNode::join(*child0, *child1);
+
+					right
+				}
+			},
+			RopeNode::None => RopeNode::None,
+		}
+	}
+
+	///Appends the contents of `other` to the end of this node.
+	pub fn append(&mut self, other: RopeNode) {
+		*self = RopeNode::join(self.take(), other);
+	}
+
+	///Concatenates two nodes, keeping the result balanced.
+	///
+	///Empty nodes are dropped rather than being given a branch, and leaves which meet at the seam are merged
+	///when they fit in a single leaf.
+	fn join(left: RopeNode, right: RopeNode) -> RopeNode {
+		match (left, right) {
+			(RopeNode::None, node) | (node, RopeNode::None) => node,
+			(RopeNode::Leaf(mut l), RopeNode::Leaf(r)) if l.len() + r.len() <= MAX_LEAF_SIZE => {
+				l.append(r);
+				RopeNode::Leaf(l)
+			},
+			(left, right) => {
+				let (left_height, right_height) = (left.height(), right.height());
+				if left_height > right_height + 1 {
+					let RopeNode::Trunk(RopeTrunkNode { child0, child1, .. }) = left else { unreachable!() };
+					RopeNode::balance(*child0, RopeNode::join(*child1, right))
+				} else if right_height > left_height + 1 {
+					let RopeNode::Trunk(RopeTrunkNode { child0, child1, .. }) = right else { unreachable!() };
+					RopeNode::balance(RopeNode::join(left, *child0), *child1)
 				} else {
-					panic!("Cannot insert a byte past the end of RopeNode::None");
+					RopeNode::Trunk(RopeTrunkNode::new(left, right))
 				}
 			},
 		}
 	}
 
+	///Builds a trunk over two subtrees whose heights differ by at most two, rotating as needed to restore balance.
+	fn balance(left: RopeNode, right: RopeNode) -> RopeNode {
+		let (left_height, right_height) = (left.height(), right.height());
+
+		if left_height > right_height + 1 {
+			let RopeNode::Trunk(RopeTrunkNode { child0: a, child1: b, .. }) = left else { unreachable!() };
+			if b.height() > a.height() {
+				//Double rotation: the inner grandchild becomes the new root.
+				let RopeNode::Trunk(RopeTrunkNode { child0: b0, child1: b1, .. }) = *b else { unreachable!() };
+				RopeNode::trunk(RopeNode::trunk(*a, *b0), RopeNode::trunk(*b1, right))
+			} else {
+				RopeNode::trunk(*a, RopeNode::trunk(*b, right))
+			}
+		} else if right_height > left_height + 1 {
+			let RopeNode::Trunk(RopeTrunkNode { child0: a, child1: b, .. }) = right else { unreachable!() };
+			if a.height() > b.height() {
+				let RopeNode::Trunk(RopeTrunkNode { child0: a0, child1: a1, .. }) = *a else { unreachable!() };
+				RopeNode::trunk(RopeNode::trunk(left, *a0), RopeNode::trunk(*a1, *b))
+			} else {
+				RopeNode::trunk(RopeNode::trunk(left, *a), *b)
+			}
+		} else {
+			RopeNode::trunk(left, right)
+		}
+	}
+
+	fn trunk(child0: RopeNode, child1: RopeNode) -> RopeNode {
+		RopeNode::Trunk(RopeTrunkNode::new(child0, child1))
+	}
+
 	pub fn collapse(self) -> Vec<u8> {
 		match self {
-			RopeNode::Leaf(l) => l.0,
+			RopeNode::Leaf(l) => l.bytes,
 			RopeNode::Trunk(t) => t.collapse_bytes(),
 			RopeNode::None => vec![],
 		}
 	}
 }
 
-impl Into<RopeLeafNode> for RopeNode {
-	fn into(self) -> RopeLeafNode {
-		match self {
+impl From<RopeNode> for RopeLeafNode {
+	fn from(value: RopeNode) -> Self {
+		match value {
 			RopeNode::Leaf(l) => l,
 			RopeNode::Trunk(RopeTrunkNode {child0, child1, ..}) => {
 				let mut out: RopeLeafNode  = (*child0).into();
@@ -194,20 +487,26 @@ impl Into<RopeLeafNode> for RopeNode {
 
 				out
 			},
-			RopeNode::None => RopeLeafNode(vec![])
+			RopeNode::None => RopeLeafNode::new(vec![])
 		}
 	}
 }
 
 impl From<String> for RopeNode {
 	fn from(value: String) -> Self {
-		RopeNode::Leaf(RopeLeafNode(value.into_bytes()))
+		RopeNode::from(value.into_bytes())
 	}
 }
 
 impl From<Vec<u8>> for RopeNode {
 	fn from(value: Vec<u8>) -> Self {
-		RopeNode::Leaf(RopeLeafNode(value))
+		if value.is_empty() {
+			RopeNode::None
+		} else if value.len() <= MAX_LEAF_SIZE {
+			RopeNode::Leaf(RopeLeafNode::new(value))
+		} else {
+			RopeNode::from_bytes(&value)
+		}
 	}
 }
 
@@ -233,30 +532,345 @@ impl Rope {
 		self.head.insert_bytes(bytes, i);
 	}
 
+	///Removes the bytes within `range`. Panics if the range extends past the end of the rope.
+	pub fn delete_range(&mut self, range: Range<usize>) {
+		self.head.delete_range(range);
+	}
+
+	///Replaces the bytes within `range` with `bytes`.
+	pub fn replace_range(&mut self, range: Range<usize>, bytes: &[u8]) {
+		self.head.replace_range(range, bytes);
+	}
+
+	///Splits the rope in two at the given index, returning everything from `i` onward.
+	pub fn split_off(&mut self, i: usize) -> Rope {
+		Rope {
+			head: Box::new(self.head.split_off(i))
+		}
+	}
+
+	///Moves the contents of `other` onto the end of this rope.
+	pub fn append(&mut self, other: Rope) {
+		self.head.append(*other.head);
+	}
+
 	pub fn len(&self) -> usize {
 		self.head.len()
 	}
 
+	///Borrows the bytes within `range` without copying them.
+	pub fn slice(&self, range: Range<usize>) -> RopeSlice<'_> {
+		if range.start > range.end || range.end > self.len() {
+			panic!("Cannot slice {range:?} from a Rope of length {}", self.len());
+		}
+		RopeSlice {
+			rope: self,
+			start: range.start,
+			end: range.end,
+		}
+	}
+
+	///Returns a copy of the bytes within `range`.
+	pub fn bytes_in(&self, range: Range<usize>) -> Vec<u8> {
+		self.slice(range).to_vec()
+	}
+
+	///Returns an iterator over the contents of the rope as contiguous byte slices, in order.
+	pub fn chunks(&self) -> Chunks<'_> {
+		self.slice(0..self.len()).chunks()
+	}
+
+	///Returns the number of lines in the rope. This is always one more than the number of newlines, so an empty rope
+	///has a single (empty) line.
+	pub fn line_count(&self) -> usize {
+		self.head.newlines() + 1
+	}
+
+	///Returns the index of the first byte of the given (zero-indexed) line.
+	pub fn line_to_byte(&self, line: usize) -> usize {
+		if line >= self.line_count() {
+			panic!("Cannot find line {line} in a Rope with {} lines", self.line_count());
+		}
+		if line == 0 {
+			0
+		} else {
+			self.head.newline_index(line - 1) + 1
+		}
+	}
+
+	///Returns the (zero-indexed) line containing the byte at `offset`. An offset of `len()` is on the last line.
+	pub fn byte_to_line(&self, offset: usize) -> usize {
+		if offset > self.len() {
+			panic!("Cannot find offset {offset} in a Rope of length {}", self.len());
+		}
+		self.head.newlines_before(offset)
+	}
+
+	///Returns the byte range of the given line, including its trailing newline (if it has one).
+	pub fn line_range(&self, line: usize) -> Range<usize> {
+		let start = self.line_to_byte(line);
+		let end = if line + 1 < self.line_count() {
+			self.line_to_byte(line + 1)
+		} else {
+			self.len()
+		};
+
+		start..end
+	}
+
+	///Returns the contents of the given line, including its trailing newline (if it has one).
+	pub fn line(&self, line: usize) -> RopeSlice<'_> {
+		self.slice(self.line_range(line))
+	}
+
+	///Returns the number of characters in the rope, as defined by [is_char_start]. Continuation bytes at the very
+	///start of the rope don't belong to any character.
+	pub fn char_count(&self) -> usize {
+		self.head.chars()
+	}
+
+	///Returns the index of the first byte of the given (zero-indexed) character. A value of `char_count()` maps to `len()`.
+	pub fn char_to_byte(&self, char_index: usize) -> usize {
+		if char_index > self.char_count() {
+			panic!("Cannot find character {char_index} in a Rope with {} characters", self.char_count());
+		}
+		self.head.char_index(char_index)
+	}
+
+	///Returns the index of the character containing the byte at `offset`.
+	pub fn byte_to_char(&self, offset: usize) -> usize {
+		if offset > self.len() {
+			panic!("Cannot find offset {offset} in a Rope of length {}", self.len());
+		}
+		if offset == self.len() {
+			self.char_count()
+		} else {
+			//The character containing `offset` is the last one to start at or before it.
+			self.head.chars_before(offset + 1).saturating_sub(1)
+		}
+	}
+
+	///Returns the offset of the next grapheme cluster boundary after `offset`, or `len()` if there is none.
+	pub fn next_grapheme_boundary(&self, offset: usize) -> usize {
+		let mut window = GRAPHEME_WINDOW;
+		loop {
+			let end = (offset + window).min(self.len());
+			let cluster = grapheme::cluster_len(&self.bytes_in(offset..end));
+			//A cluster which fills the window might continue past it.
+			if offset + cluster < end || end == self.len() {
+				return offset + cluster;
+			}
+			window *= 2;
+		}
+	}
+
+	///Returns the offset of the grapheme cluster boundary before `offset`, or 0 if there is none.
+	pub fn prev_grapheme_boundary(&self, offset: usize) -> usize {
+		if offset == 0 {
+			return 0;
+		}
+		//Line starts are always boundaries, so segmenting from there is exact. Very long lines are only
+		//segmented from a nearby character boundary instead.
+		let mut start = self.line_to_byte(self.byte_to_line(offset - 1)).max(offset.saturating_sub(GRAPHEME_WINDOW));
+		while start > 0 && !is_char_start(self.byte_at(start)) {
+			start -= 1;
+		}
+
+		let mut boundary = start;
+		for cluster in grapheme::clusters(&self.bytes_in(start..offset)) {
+			if boundary + cluster.len() >= offset {
+				break;
+			}
+			boundary += cluster.len();
+		}
+
+		boundary
+	}
+
+	///Returns an iterator over the characters of the rope. Characters which aren't valid UTF-8 are replaced with `U+FFFD`.
+	pub fn chars(&self) -> RopeChars<'_> {
+		self.slice(0..self.len()).chars()
+	}
+
 	///Destroys self, returning the flattened contents of this tree.
 	pub fn collapse(self) -> Vec<u8> {
 		self.head.collapse()
 	}
 }
 
-pub struct RopeIterator<'a> {
+///How many bytes around an offset are inspected when looking for a grapheme cluster boundary.
+const GRAPHEME_WINDOW: usize = 64;
+
+///A borrowed view of a range of bytes within a [Rope], created by [Rope::slice].
+#[derive(Debug, Clone, Copy)]
+pub struct RopeSlice<'a> {
 	rope: &'a Rope,
-	index: usize,
-} impl<'a> Iterator for RopeIterator<'a> {
+	start: usize,
+	end: usize,
+} impl<'a> RopeSlice<'a> {
+	pub fn len(&self) -> usize {
+		self.end - self.start
+	}
+	pub fn is_empty(&self) -> bool {
+		self.start == self.end
+	}
+	///Returns the range of bytes this slice covers within its rope.
+	pub fn range(&self) -> Range<usize> {
+		self.start..self.end
+	}
+	pub fn byte_at(&self, i: usize) -> u8 {
+		if i >= self.len() {
+			panic!("Cannot read byte {i} of a RopeSlice of length {}", self.len());
+		}
+		self.rope.byte_at(self.start + i)
+	}
+
+	///Borrows a sub-range of this slice. `range` is relative to the start of the slice.
+	pub fn slice(&self, range: Range<usize>) -> RopeSlice<'a> {
+		if range.start > range.end || range.end > self.len() {
+			panic!("Cannot slice {range:?} from a RopeSlice of length {}", self.len());
+		}
+		RopeSlice {
+			rope: self.rope,
+			start: self.start + range.start,
+			end: self.start + range.end,
+		}
+	}
+
+	///Returns an iterator over the contents of the slice as contiguous byte slices, in order.
+	pub fn chunks(&self) -> Chunks<'a> {
+		Chunks {
+			head: &self.rope.head,
+			start: self.start,
+			end: self.end,
+		}
+	}
+	pub fn bytes(&self) -> RopeIterator<'a> {
+		RopeIterator(self.chunks().flatten().copied())
+	}
+	///Returns an iterator over the characters starting within the slice. Characters which aren't valid UTF-8 are replaced
+	///with `U+FFFD`.
+	pub fn chars(&self) -> RopeChars<'a> {
+		RopeChars {
+			bytes: self.bytes().peekable(),
+		}
+	}
+	///Returns an iterator over the grapheme clusters of the slice.
+	pub fn graphemes(&self) -> Graphemes<'a> {
+		Graphemes {
+			rope: self.rope,
+			chunks: self.chunks(),
+			rest: &[],
+			offset: self.start,
+			end: self.end,
+		}
+	}
+	pub fn to_vec(self) -> Vec<u8> {
+		let mut out = Vec::with_capacity(self.len());
+		for chunk in self.chunks() {
+			out.extend_from_slice(chunk);
+		}
+
+		out
+	}
+}
+
+impl<'a> IntoIterator for RopeSlice<'a> {
+	type IntoIter = RopeIterator<'a>;
 	type Item = u8;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.bytes()
+	}
+}
+
+///Iterator over the leaves of a [Rope] (or [RopeSlice]) as byte slices, created by [Rope::chunks].
+///
+///Each step looks up the leaf from the root, so iterating costs O(log n) per chunk rather than per byte.
+pub struct Chunks<'a> {
+	head: &'a RopeNode,
+	start: usize,
+	end: usize,
+} impl<'a> Iterator for Chunks<'a> {
+	type Item = &'a [u8];
 	fn next(&mut self) -> Option<Self::Item> {
-		if self.index >= self.rope.len() {
-			None
-		} else {
-			let v = self.rope.byte_at(self.index);
-			self.index += 1;
+		if self.start >= self.end {
+			return None;
+		}
+		let (leaf, leaf_start) = self.head.leaf_at(self.start);
+		let chunk = &leaf[self.start - leaf_start..(self.end - leaf_start).min(leaf.len())];
+		self.start += chunk.len();
+
+		Some(chunk)
+	}
+} impl<'a> DoubleEndedIterator for Chunks<'a> {
+	fn next_back(&mut self) -> Option<Self::Item> {
+		if self.start >= self.end {
+			return None;
+		}
+		let (leaf, leaf_start) = self.head.leaf_at(self.end - 1);
+		let from = self.start.max(leaf_start);
+		let chunk = &leaf[from - leaf_start..self.end - leaf_start];
+		self.end = from;
+
+		Some(chunk)
+	}
+}
+
+///Iterator over the grapheme clusters of a [RopeSlice], created by [RopeSlice::graphemes].
+///
+///Clusters are borrowed from the rope's leaves, and only copied when they straddle two of them.
+pub struct Graphemes<'a> {
+	rope: &'a Rope,
+	chunks: Chunks<'a>,
+	///What is left of the current chunk, which starts at `offset` within the rope.
+	rest: &'a [u8],
+	offset: usize,
+	end: usize,
+} impl<'a> Iterator for Graphemes<'a> {
+	type Item = Cow<'a, [u8]>;
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.rest.is_empty() {
+			self.rest = self.chunks.next()?;
+		}
+
+		let len = grapheme::cluster_len(self.rest);
+		//A cluster which runs to the end of the chunk might carry on into the next one.
+		if len < self.rest.len() || self.offset + len == self.end {
+			let (cluster, rest) = self.rest.split_at(len);
+			self.rest = rest;
+			self.offset += len;
+			return Some(Cow::Borrowed(cluster));
+		}
 
-			Some(v)
+		let end = self.rope.next_grapheme_boundary(self.offset).min(self.end);
+		let cluster = self.rope.bytes_in(self.offset..end);
+		let mut skip = end - self.offset - self.rest.len();
+		self.rest = &[];
+		while skip > 0 {
+			let chunk = self.chunks.next().expect("the rest of the cluster to be within the slice");
+			if chunk.len() > skip {
+				self.rest = &chunk[skip..];
+				break;
+			}
+			skip -= chunk.len();
 		}
+		self.offset = end;
+
+		Some(Cow::Owned(cluster))
+	}
+}
+
+///Iterator over the bytes of a [Rope] (or [RopeSlice]).
+pub struct RopeIterator<'a>(Copied<Flatten<Chunks<'a>>>);
+impl<'a> Iterator for RopeIterator<'a> {
+	type Item = u8;
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()
+	}
+} impl<'a> DoubleEndedIterator for RopeIterator<'a> {
+	fn next_back(&mut self) -> Option<Self::Item> {
+		self.0.next_back()
 	}
 }
 
@@ -265,16 +879,102 @@ impl<'a> IntoIterator for &'a Rope {
 	type Item = u8;
 
 	fn into_iter(self) -> Self::IntoIter {
-		RopeIterator {
-			rope: self,
-			index: 0,
+		self.slice(0..self.len()).bytes()
+	}
+}
+
+///Iterator over the characters of a [Rope], created by [Rope::chars].
+///
+///Characters are split up as described in [is_char_start], so that there are as many as [Rope::char_count] says.
+pub struct RopeChars<'a> {
+	bytes: Peekable<RopeIterator<'a>>,
+} impl<'a> Iterator for RopeChars<'a> {
+	type Item = char;
+	fn next(&mut self) -> Option<Self::Item> {
+		//Continuation bytes before the first character start don't belong to any character.
+		let lead = loop {
+			let byte = self.bytes.next()?;
+			if is_char_start(byte) {
+				break byte;
+			}
+		};
+
+		//Only the first four bytes are kept, as longer sequences are invalid anyway.
+		let mut sequence = [lead, 0, 0, 0];
+		let mut width = 1;
+		while let Some(byte) = self.bytes.next_if(|b| !is_char_start(*b)) {
+			if width < 4 {
+				sequence[width] = byte;
+			}
+			width += 1;
+		}
+
+		Some(decode(&sequence, width))
+	}
+} impl<'a> DoubleEndedIterator for RopeChars<'a> {
+	fn next_back(&mut self) -> Option<Self::Item> {
+		//Continuation bytes belong to the closest character start before them. If there is none, they don't belong to
+		//any character and are dropped.
+		let mut sequence = [0; 4];
+		let mut width = 0;
+		loop {
+			let byte = self.bytes.next_back()?;
+			if width < 4 {
+				sequence[3 - width] = byte;
+			}
+			width += 1;
+			if is_char_start(byte) {
+				break;
+			}
 		}
+
+		Some(decode(&sequence[4 - width.min(4)..], width))
+	}
+}
+
+///Decodes a character `width` bytes long, the first of which are in `sequence`, returning `U+FFFD` if it isn't valid UTF-8.
+fn decode(sequence: &[u8], width: usize) -> char {
+	if width > 4 {
+		return char::REPLACEMENT_CHARACTER;
 	}
+	std::str::from_utf8(&sequence[..width]).map_or(char::REPLACEMENT_CHARACTER, |s| s.chars().next().expect("a decoded sequence to contain a character"))
 }
 
 #[cfg(test)]
 mod test {
-    use super::Rope;
+    use std::borrow::Cow;
+
+    use super::{Rope, RopeNode, MAX_LEAF_SIZE};
+
+	///Checks the structural invariants of a subtree, returning its length and height.
+	fn check(node: &RopeNode) -> (usize, usize) {
+		match node {
+			RopeNode::Leaf(l) => {
+				assert!(l.len() != 0 && l.len() <= MAX_LEAF_SIZE);
+				assert_eq!(l.newlines, super::count_newlines(&l.bytes));
+				assert_eq!(l.chars, super::count_chars(&l.bytes));
+				(l.len(), 1)
+			},
+			RopeNode::Trunk(t) => {
+				assert!(!matches!(*t.child0, RopeNode::None) && !matches!(*t.child1, RopeNode::None));
+				let (len0, height0) = check(&t.child0);
+				let (len1, height1) = check(&t.child1);
+				assert_eq!(t.length, len0 + len1);
+				assert_eq!(t.newlines, t.child0.newlines() + t.child1.newlines());
+				assert_eq!(t.chars, t.child0.chars() + t.child1.chars());
+				assert_eq!(t.height, height0.max(height1) + 1);
+				assert!(height0.abs_diff(height1) <= 1);
+				(t.length, t.height)
+			},
+			RopeNode::None => (0, 0),
+		}
+	}
+
+	///Tiny deterministic PRNG so the randomized tests are reproducible.
+	fn lcg(seed: &mut u64) -> usize {
+		*seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+		(*seed >> 33) as usize
+	}
 
 	#[test]
 	fn insert_byte() {
@@ -307,5 +1007,321 @@ mod test {
 		r.insert_byte(b'A', 1);
 	}
 
-	
-}
\ No newline at end of file
+	#[test]
+	fn delete_range() {
+		let mut r = Rope::new();
+
+		r.insert_bytes(b"Hello, World!", 0);
+		r.insert_bytes(b" Rusty", 6);
+		r.delete_range(5..12);
+		assert_eq!(r.len(), 12);
+		assert_eq!(r.clone().collapse(), b"Hello World!");
+
+		r.delete_range(0..6);
+		assert_eq!(r.len(), 6);
+		assert_eq!(r.clone().collapse(), b"World!");
+
+		r.delete_range(0..6);
+		assert_eq!(r.len(), 0);
+		assert_eq!(r.collapse(), b"");
+	}
+
+	#[test]
+	#[should_panic]
+	fn delete_past_end() {
+		let mut r = Rope::new();
+
+		r.insert_bytes(b"abc", 0);
+		r.delete_range(1..4);
+	}
+
+	#[test]
+	fn replace_range() {
+		let mut r = Rope::new();
+
+		r.insert_bytes(b"Hello, World!", 0);
+		r.replace_range(7..12, b"Rope");
+		assert_eq!(r.len(), 12);
+		assert_eq!(r.collapse(), b"Hello, Rope!");
+	}
+
+	#[test]
+	fn split_and_append() {
+		let mut r = Rope::new();
+
+		r.insert_bytes(b"World!", 0);
+		r.insert_bytes(b"Hello, ", 0);
+		let mut tail = r.split_off(4);
+		assert_eq!(r.len(), 4);
+		assert_eq!(tail.len(), 9);
+		assert_eq!(r.clone().collapse(), b"Hell");
+		assert_eq!(tail.clone().collapse(), b"o, World!");
+
+		tail.append(r);
+		assert_eq!(tail.len(), 13);
+		assert_eq!(tail.collapse(), b"o, World!Hell");
+	}
+
+	#[test]
+	fn stays_balanced() {
+		let mut r = Rope::new();
+
+		for i in 0..100_000 {
+			r.insert_byte(b'a' + (i % 26) as u8, 0);
+		}
+		let (len, height) = check(&r.head);
+		assert_eq!(len, 100_000);
+		//A perfectly balanced tree of full leaves would be 8 levels deep; AVL allows ~1.44x that.
+		assert!(height <= 20, "height {height} is too large");
+
+		let large = vec![b'x'; 5 * 1024 * 1024];
+		r.insert_bytes(&large, 50_000);
+		let (len, height) = check(&r.head);
+		assert_eq!(len, 100_000 + large.len());
+		assert!(height <= 30, "height {height} is too large");
+	}
+
+	#[test]
+	fn random_edits() {
+		let mut seed = 0x5eed;
+		let mut r = Rope::new();
+		let mut model: Vec<u8> = Vec::new();
+
+		for step in 0..5_000 {
+			let at = lcg(&mut seed) % (model.len() + 1);
+			match lcg(&mut seed) % 3 {
+				0 | 1 => {
+					let bytes: Vec<u8> = "ab\ncdé€".bytes().cycle().skip(lcg(&mut seed) % 10).take(lcg(&mut seed) % 1500).collect();
+					r.insert_bytes(&bytes, at);
+					model.splice(at..at, bytes);
+				},
+				_ => {
+					let end = (at + lcg(&mut seed) % 2000).min(model.len());
+					r.delete_range(at..end);
+					model.drain(at..end);
+				},
+			}
+			if step % 100 == 0 {
+				check(&r.head);
+			}
+		}
+		check(&r.head);
+		assert_eq!(r.collapse(), model);
+	}
+
+	#[test]
+	fn lines() {
+		let mut r = Rope::new();
+		assert_eq!(r.line_count(), 1);
+		assert_eq!(r.line(0).to_vec(), b"");
+
+		r.insert_bytes(b"first\nsecond\n\nfourth", 0);
+		assert_eq!(r.line_count(), 4);
+		assert_eq!(r.line_to_byte(0), 0);
+		assert_eq!(r.line_to_byte(1), 6);
+		assert_eq!(r.line_to_byte(2), 13);
+		assert_eq!(r.line_to_byte(3), 14);
+		assert_eq!(r.byte_to_line(0), 0);
+		assert_eq!(r.byte_to_line(5), 0);
+		assert_eq!(r.byte_to_line(6), 1);
+		assert_eq!(r.byte_to_line(13), 2);
+		assert_eq!(r.byte_to_line(r.len()), 3);
+		assert_eq!(r.line(1).to_vec(), b"second\n");
+		assert_eq!(r.line(2).to_vec(), b"\n");
+		assert_eq!(r.line(3).to_vec(), b"fourth");
+
+		r.delete_range(6..13);
+		assert_eq!(r.line_count(), 3);
+		assert_eq!(r.line(0).to_vec(), b"first\n");
+		assert_eq!(r.line(1).to_vec(), b"\n");
+	}
+
+	#[test]
+	fn lines_across_leaves() {
+		let mut r = Rope::new();
+		let mut text = Vec::new();
+		for i in 0..10_000 {
+			text.extend_from_slice(format!("line {i}\n").as_bytes());
+		}
+		r.insert_bytes(&text, 0);
+		check(&r.head);
+
+		assert_eq!(r.line_count(), 10_001);
+		for i in [0, 1, 99, 1234, 9999] {
+			let start = r.line_to_byte(i);
+			assert_eq!(r.byte_to_line(start), i);
+			assert_eq!(r.line(i).to_vec(), format!("line {i}\n").as_bytes());
+		}
+		assert_eq!(r.line(10_000).to_vec(), b"");
+	}
+
+	#[test]
+	#[should_panic]
+	fn line_past_end() {
+		let mut r = Rope::new();
+
+		r.insert_bytes(b"one\ntwo", 0);
+		r.line_to_byte(2);
+	}
+
+	#[test]
+	fn chars() {
+		let mut r = Rope::new();
+
+		r.insert_bytes("añ€😀b".as_bytes(), 0);
+		assert_eq!(r.len(), 11);
+		assert_eq!(r.char_count(), 5);
+		assert_eq!(r.chars().collect::<String>(), "añ€😀b");
+		assert_eq!(r.char_to_byte(0), 0);
+		assert_eq!(r.char_to_byte(2), 3);
+		assert_eq!(r.char_to_byte(4), 10);
+		assert_eq!(r.char_to_byte(5), 11);
+		assert_eq!(r.byte_to_char(3), 2);
+		assert_eq!(r.byte_to_char(4), 2);
+		assert_eq!(r.byte_to_char(9), 3);
+		assert_eq!(r.byte_to_char(11), 5);
+
+		r.insert_bytes(b"\xFF", 1);
+		assert_eq!(r.char_count(), 6);
+		assert_eq!(r.chars().collect::<String>(), "a\u{FFFD}ñ€😀b");
+	}
+
+	#[test]
+	fn chars_across_leaves() {
+		let mut r = Rope::new();
+		let text = "ü€".repeat(2000);
+		r.insert_bytes(text.as_bytes(), 0);
+		check(&r.head);
+
+		assert_eq!(r.char_count(), 4000);
+		assert_eq!(r.chars().collect::<String>(), text);
+		for c in [0, 1, 1001, 3999] {
+			let b = r.char_to_byte(c);
+			assert_eq!(r.byte_to_char(b), c);
+			assert_eq!(b, text.char_indices().nth(c).unwrap().0);
+		}
+	}
+
+	#[test]
+	fn graphemes() {
+		let mut r = Rope::new();
+
+		//"e" + combining acute, a flag made of two regional indicators, a ZWJ family emoji, and CRLF.
+		let text = "e\u{301}x🇳🇱👩\u{200D}👩\u{200D}👧\r\ny";
+		r.insert_bytes(text.as_bytes(), 0);
+
+		let mut boundaries = vec![0];
+		while *boundaries.last().unwrap() < r.len() {
+			boundaries.push(r.next_grapheme_boundary(*boundaries.last().unwrap()));
+		}
+		assert_eq!(boundaries, vec![0, 3, 4, 12, 30, 32, 33]);
+
+		let mut back = vec![r.len()];
+		while *back.last().unwrap() > 0 {
+			back.push(r.prev_grapheme_boundary(*back.last().unwrap()));
+		}
+		back.reverse();
+		assert_eq!(back, boundaries);
+
+		let clusters: Vec<Cow<[u8]>> = r.slice(0..r.len()).graphemes().collect();
+		assert_eq!(clusters.iter().map(|c| c.len()).collect::<Vec<usize>>(), vec![3, 1, 8, 18, 2, 1]);
+		assert_eq!(clusters.concat(), text.as_bytes());
+	}
+
+	#[test]
+	fn graphemes_across_leaves() {
+		let mut r = Rope::new();
+		let text = "e\u{301}".repeat(5000);
+		r.insert_bytes(text.as_bytes(), 0);
+
+		//Leaves don't line up with the three byte clusters, so some have to be stitched back together.
+		let clusters: Vec<Cow<[u8]>> = r.slice(3..r.len()).graphemes().collect();
+		assert_eq!(clusters.len(), 4999);
+		assert!(clusters.iter().all(|c| **c == *"e\u{301}".as_bytes()));
+		assert!(clusters.iter().any(|c| matches!(c, Cow::Owned(_))));
+	}
+
+	#[test]
+	fn chunks() {
+		let mut r = Rope::new();
+		let text: Vec<u8> = (0..10_000).map(|i| b'a' + (i % 26) as u8).collect();
+		r.insert_bytes(&text, 0);
+
+		let chunks: Vec<&[u8]> = r.chunks().collect();
+		assert!(chunks.len() > 1);
+		assert!(chunks.iter().all(|c| !c.is_empty()));
+		assert_eq!(chunks.concat(), text);
+
+		let mut reversed: Vec<&[u8]> = r.chunks().rev().collect();
+		reversed.reverse();
+		assert_eq!(reversed, chunks);
+	}
+
+	#[test]
+	fn slices() {
+		let mut r = Rope::new();
+		let text: Vec<u8> = (0..10_000).map(|i| b'a' + (i % 26) as u8).collect();
+		r.insert_bytes(&text, 0);
+
+		let slice = r.slice(1500..8000);
+		assert_eq!(slice.len(), 6500);
+		assert_eq!(slice.to_vec(), &text[1500..8000]);
+		assert_eq!(slice.byte_at(0), text[1500]);
+		assert_eq!(slice.chunks().rev().flat_map(|c| c.iter().rev().copied()).collect::<Vec<u8>>(), text[1500..8000].iter().rev().copied().collect::<Vec<u8>>());
+
+		let inner = slice.slice(10..20);
+		assert_eq!(inner.range(), 1510..1520);
+		assert_eq!(inner.bytes().collect::<Vec<u8>>(), &text[1510..1520]);
+		assert_eq!(inner.bytes().rev().collect::<Vec<u8>>(), text[1510..1520].iter().rev().copied().collect::<Vec<u8>>());
+		assert!(r.slice(5..5).is_empty());
+		assert_eq!(r.slice(5..5).chunks().count(), 0);
+	}
+
+	#[test]
+	fn chars_reversed() {
+		let mut r = Rope::new();
+
+		r.insert_bytes("añ€😀b".as_bytes(), 0);
+		assert_eq!(r.chars().rev().collect::<String>(), "b😀€ña");
+		assert_eq!(r.slice(1..6).chars().rev().collect::<String>(), "€ñ");
+	}
+
+	#[test]
+	fn invalid_chars() {
+		//Stray continuation bytes (at the start, after ASCII and after complete sequences), truncated and overlong
+		//sequences, and bytes which can never appear in UTF-8.
+		let samples: [&[u8]; 6] = [
+			b"\x80\x80a\x80b",
+			b"\xC3\xA9\xA9\xE2\x82 \xF0\x9F\x98\x80\x80\x80",
+			b"\xFF\xFE\xC0\xAF\xE0\x80\x80x",
+			b"\xE2\x82\xAC\xBF\xBF\xBF\xBF",
+			b"\x80",
+			b"",
+		];
+		for sample in samples {
+			let mut r = Rope::new();
+			r.insert_bytes(sample, 0);
+
+			let forward: Vec<char> = r.chars().collect();
+			let mut backward: Vec<char> = r.chars().rev().collect();
+			backward.reverse();
+			assert_eq!(r.char_count(), forward.len(), "{sample:?}");
+			assert_eq!(backward, forward, "{sample:?}");
+			for i in 0..r.char_count() {
+				assert_eq!(r.byte_to_char(r.char_to_byte(i)), i, "{sample:?}");
+			}
+		}
+
+		let mut r = Rope::new();
+		r.insert_bytes(b"\x80a\xC3\xA9\xA9b", 0);
+		assert_eq!(r.chars().collect::<String>(), "a\u{FFFD}b");
+
+		//The same holds across leaves.
+		let text = b"\xE2\x82\xAC\xBFa\x80".repeat(1000);
+		r = Rope::new();
+		r.insert_bytes(&text, 0);
+		assert_eq!(r.char_count(), 2000);
+		assert_eq!(r.chars().count(), 2000);
+		assert_eq!(r.chars().rev().count(), 2000);
+	}
+}
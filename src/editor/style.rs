@@ -1,10 +1,10 @@
-use std::fmt::Display;
+use std::path::PathBuf;
 
 use serde::Deserialize;
 
-use crate::terminil::prelude::*;
+use crate::terminil::{prelude::*, width};
 
-use super::Editor;
+use super::{xdg_dir, Editor};
 
 #[derive(Deserialize, Debug)]
 pub struct Statusbar {
@@ -12,64 +12,107 @@ pub struct Statusbar {
 	foreground: Color,
 	modules: Vec<StatusbarModule>,
 } impl Statusbar {
-	pub fn render(&self, context: &Editor) -> String {
+	///Lays the statusbar out across `width` cells, returning each piece of text with its position and the module it
+	///came from.
+	///
+	///Modules are laid out in sections separated by `Flex` modules. The first section is aligned to the left edge, the
+	///last to the right edge, and any in between are centred at even intervals.
+	fn layout(&self, context: &Editor, width: u16) -> Vec<(u16, String, Style, &StatusbarModule)> {
+		let base = self.base_style();
 		let mut sections = Vec::new();
-		let mut section = String::new();
+		let mut section = Vec::new();
 
 		for module in &self.modules {
-			match module {
+			let text = match module {
 				StatusbarModule::Flex => {
-					sections.push(section);
-					section = String::new();
+					sections.push(std::mem::take(&mut section));
+					continue;
 				},
 				StatusbarModule::Text { content, style } => {
-					section += content;
+					section.push((content.clone(), style.map_or(base, |s| s.inherit(base)), module));
+					continue;
 				},
 				StatusbarModule::Mode => {
 					use super::Mode;
-					section += match context.mode {
+					match context.mode {
 						Mode::Normal => "Normal",
 						Mode::Insert => "Insert",
 						Mode::Select => "Select",
-					}
+					}.to_string()
+				},
+				StatusbarModule::Row => {
+					(context.buffers[context.current_buffer].cursor().0 + 1).to_string()
+				},
+				StatusbarModule::Column => {
+					(context.buffers[context.current_buffer].cursor().1 + 1).to_string()
+				},
+				StatusbarModule::RowPercent => {
+					let buffer = &context.buffers[context.current_buffer];
+					let percent = (buffer.cursor().0 + 1) * 100 / buffer.rope().line_count();
+					format!("{percent}%")
+				},
+				StatusbarModule::Filename => {
+					context.buffers[context.current_buffer].name().to_string()
+				},
+				StatusbarModule::Modified => {
+					if context.buffers[context.current_buffer].is_modified() { "[+]" } else { "" }.to_string()
 				},
 				StatusbarModule::Whitespace { length } => {
-					section += &(" ").repeat(*length as usize);
+					(" ").repeat(*length as usize)
 				},
-				_ => {
-					section += "@";
-				}
-			}
+			};
+			section.push((text, base, module));
 		}
 		sections.push(section);
 		if sections.len() == 1 {
-			sections.push(String::new());
+			sections.push(Vec::new());
 		}
 
-		use crate::terminil::ansi::strip;
-
-		let width = context.input.dimensions().0;
-
-		let mut out = self.background.as_bg() + &self.foreground.as_fg() + &Action::EraseToLineEnd.to_ansi();
-
 		let spacing = width / (sections.len()-1) as u16;
 		let section_count = sections.len();
+		let mut pieces = Vec::new();
 
 		for (i, section) in sections.into_iter().enumerate() {
-			if i == 0 {
-				out += &format!("{}{section}", Motion::LineStart.to_ansi());
+			let len = section.iter().map(|(text, ..)| width::str_width(text) as u16).sum::<u16>();
+			let mut x = if i == 0 {
+				0
 			} else if i == section_count - 1 {
-				out += &format!("{}{section}", Motion::LineAbsolute(width-strip(&section).len() as u16).to_ansi());
+				width.saturating_sub(len)
 			} else {
-				let start = (i as u16) * spacing - (strip(&section).len() as u16)/2;
-				out += &format!("{}{section}", Motion::LineAbsolute(start).to_ansi());
+				((i as u16) * spacing).saturating_sub(len/2)
+			};
+			for (text, style, module) in section {
+				let next = x.saturating_add(width::str_width(&text) as u16);
+				pieces.push((x, text, style, module));
+				x = next;
 			}
 		}
 
-		out
+		pieces
+	}
+	fn base_style(&self) -> Style {
+		Style::fg(self.foreground).inherit(Style::bg(self.background))
+	}
+	///Draws the statusbar across line `y` of `screen`.
+	pub fn render(&self, context: &Editor, screen: &mut Screen, y: u16) {
+		let (width, _) = screen.size();
+		for x in 0..width {
+			screen.put(x, y, " ", self.base_style());
+		}
+
+		for (x, text, style, _) in self.layout(context, width) {
+			screen.print(x, y, &text, style);
+		}
+	}
+	///Returns the module shown at column `x` of a statusbar `width` cells wide.
+	pub fn module_at(&self, context: &Editor, width: u16, x: u16) -> Option<&StatusbarModule> {
+		self.layout(context, width).into_iter()
+			.find(|(start, text, ..)| (*start..start + width::str_width(text) as u16).contains(&x))
+			.map(|(.., module)| module)
 	}
 }
 
+#[allow(unused)]
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "lowercase")]
 #[serde(tag = "type")]
@@ -80,14 +123,20 @@ pub enum StatusbarModule {
 	Row,
 	Column,
 	Filename,
+	///How far through the buffer the cursor is, as a percentage of its lines.
 	RowPercent,
 	Mode,
+	///Shows `[+]` when the current buffer has unsaved changes.
+	Modified,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Stylesheet {
 	pub background: Color,
 	pub text: Color,
+	///The background of selected text.
+	#[serde(default = "default_selection")]
+	pub selection: Color,
 
 	pub statusbar: Statusbar
 } impl Default for Stylesheet {
@@ -95,6 +144,7 @@ pub struct Stylesheet {
 		Stylesheet {
 			background: Color::Uncolored,
 			text: Color::Uncolored,
+			selection: default_selection(),
 			statusbar: Statusbar {
 				background: Color::White,
 				foreground: Color::Black,
@@ -103,8 +153,83 @@ pub struct Stylesheet {
 					StatusbarModule::Mode,
 					StatusbarModule::Whitespace { length: 1 },
 					StatusbarModule::Filename,
+					StatusbarModule::Whitespace { length: 1 },
+					StatusbarModule::Modified,
 				]
 			}
 		}
 	}
-}
\ No newline at end of file
+}
+
+fn default_selection() -> Color {
+	Color::BrightBlack
+}
+
+///The theme used when no other is chosen, which is built into the editor.
+const BUILTIN_THEME: &str = include_str!("../../theme.toml");
+///The name of the built-in theme. A theme of the same name in the themes directory takes its place.
+pub const DEFAULT_THEME: &str = "default";
+
+///Returns the directory themes are looked up by name in: `$XDG_CONFIG_HOME/edit-rust/themes`, or
+///`~/.config/edit-rust/themes` if that isn't set.
+pub fn themes_dir() -> Option<PathBuf> {
+	Some(xdg_dir("XDG_CONFIG_HOME", ".config")?.join("themes"))
+}
+
+impl Stylesheet {
+	///Loads the theme called `theme` from [themes_dir], or from the file at `theme` if it looks like a path (it
+	///contains a `/` or ends in `.toml`). Errors are described on a single line, so they fit in a message.
+	pub fn load(theme: &str) -> Result<Stylesheet, String> {
+		let path = if theme.contains('/') || theme.ends_with(".toml") {
+			PathBuf::from(theme)
+		} else {
+			match themes_dir().map(|dir| dir.join(format!("{theme}.toml"))) {
+				Some(path) if path.exists() => path,
+				_ if theme == DEFAULT_THEME => {
+					return Stylesheet::parse(BUILTIN_THEME).map_err(|e| format!("Could not load the built-in theme: {e}"));
+				},
+				Some(path) => path,
+				None => return Err(format!("Could not find theme {theme}, as there is no home directory to look in")),
+			}
+		};
+
+		let text = std::fs::read_to_string(&path).map_err(|e| format!("Could not read theme {}: {e}", path.display()))?;
+		Stylesheet::parse(&text).map_err(|e| format!("Could not load theme {}: {e}", path.display()))
+	}
+	///Parses a theme, describing any error by its line and column.
+	fn parse(text: &str) -> Result<Stylesheet, String> {
+		toml::from_str(text).map_err(|e| {
+			let message = e.message().replace('\n', " ");
+			match e.span() {
+				Some(span) => {
+					let before = &text[..span.start];
+					let line = before.matches('\n').count() + 1;
+					let col = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+					format!("line {line}, column {col}: {message}")
+				},
+				None => message,
+			}
+		})
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{Stylesheet, BUILTIN_THEME};
+
+	#[test]
+	fn builtin_theme() {
+		assert!(Stylesheet::parse(BUILTIN_THEME).is_ok());
+	}
+
+	#[test]
+	fn describe_errors() {
+		let error = Stylesheet::parse("background = 0\ntext = ?\n").unwrap_err();
+		assert!(error.starts_with("line 2, column 8:"), "{error}");
+		let error = Stylesheet::parse("background = 0\ntext = 1\n").unwrap_err();
+		assert!(error.contains("statusbar"), "{error}");
+
+		let error = Stylesheet::load("/nonexistent/theme.toml").unwrap_err();
+		assert!(error.starts_with("Could not read theme /nonexistent/theme.toml"), "{error}");
+	}
+}
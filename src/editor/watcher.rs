@@ -0,0 +1,108 @@
+//! Notices when open files are changed by other programs.
+//!
+//! A single background thread polls every watched file. Files are only read (and hashed) once their modification time
+//! or size changes, so the editor itself never has to touch the disk to find out.
+
+use std::{collections::HashMap, sync::{Arc, Mutex, Weak}, time::{Duration, SystemTime}};
+
+use super::{history, File};
+
+///What a file's metadata looked like when it was last checked, or `None` if it couldn't be read.
+type Stamp = Option<(Option<SystemTime>, u64)>;
+
+fn stamp(path: &str) -> Stamp {
+	std::fs::metadata(path).ok().map(|m| (m.modified().ok(), m.len()))
+}
+
+pub struct FileWatcher {
+	paths: Arc<Mutex<HashMap<String, Stamp>>>,
+} impl FileWatcher {
+	///Starts watching, checking files every `interval`. Whenever a file changes, `changed` is called from the
+	///background thread with its path and [content hash](history::content_hash). Watching stops once `changed` returns
+	///false or the watcher is dropped.
+	pub fn new(interval: Duration, changed: impl Fn(String, u64) -> bool + Send + 'static) -> Self {
+		let paths = Arc::new(Mutex::new(HashMap::new()));
+		let weak = Arc::downgrade(&paths);
+		std::thread::spawn(move || watch(weak, interval, changed));
+
+		FileWatcher { paths }
+	}
+	///Makes `paths` the set of files being watched. Files which were already watched keep their state, and new ones
+	///only count as changed once they change after this call.
+	pub fn set_paths<'a>(&self, paths: impl IntoIterator<Item = &'a str>) {
+		let mut watched = self.paths.lock().expect("the watcher thread not to panic");
+		let mut new = HashMap::new();
+		for path in paths {
+			let stamp = watched.remove(path).unwrap_or_else(|| stamp(path));
+			new.insert(path.to_string(), stamp);
+		}
+		*watched = new;
+	}
+}
+
+fn watch(paths: Weak<Mutex<HashMap<String, Stamp>>>, interval: Duration, changed: impl Fn(String, u64) -> bool) {
+	loop {
+		std::thread::sleep(interval);
+		let Some(paths) = paths.upgrade() else {
+			return;
+		};
+
+		//The lock isn't held while checking, so the editor never waits on the disk.
+		let watched: Vec<(String, Stamp)> = paths.lock().expect("the editor not to panic").iter().map(|(p, s)| (p.clone(), *s)).collect();
+		for (path, last) in watched {
+			let current = stamp(&path);
+			if current == last {
+				continue;
+			}
+			if let Some(stamp) = paths.lock().expect("the editor not to panic").get_mut(&path) {
+				*stamp = current;
+			}
+
+			let Ok(rope) = File::open(&path, true).read_to_rope() else {
+				continue;
+			};
+			if !changed(path, history::content_hash(&rope)) {
+				return;
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{sync::mpsc, time::Duration};
+
+	use super::FileWatcher;
+	use crate::editor::{history::content_hash, rope::Rope};
+
+	#[test]
+	fn notice_changes() {
+		let dir = std::env::temp_dir().join(format!("edit-rust-watcher-test-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let (watched, other) = (dir.join("watched.txt"), dir.join("other.txt"));
+		std::fs::write(&watched, "one").unwrap();
+		std::fs::write(&other, "one").unwrap();
+
+		let (sender, receiver) = mpsc::channel();
+		let watcher = FileWatcher::new(Duration::from_millis(10), move |path, hash| sender.send((path, hash)).is_ok());
+		watcher.set_paths([watched.to_str().unwrap()]);
+		std::thread::sleep(Duration::from_millis(50));
+		assert!(receiver.try_recv().is_err());
+
+		//The size changes too, in case the file system's timestamps are too coarse to tell.
+		std::fs::write(&other, "other").unwrap();
+		std::fs::write(&watched, "three").unwrap();
+		let mut rope = Rope::new();
+		rope.insert_bytes(b"three", 0);
+		let expected = (watched.to_string_lossy().to_string(), content_hash(&rope));
+		assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(expected));
+
+		//Files which are no longer watched aren't reported.
+		watcher.set_paths([]);
+		std::fs::write(&watched, "four!").unwrap();
+		std::thread::sleep(Duration::from_millis(50));
+		assert!(receiver.try_recv().is_err());
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -1,29 +1,165 @@
-use std::io::IsTerminal;
+use std::{fs::OpenOptions, io::{IsTerminal, Read, Write}, ops::ControlFlow, process::ExitCode};
 
-use editor::{style::Stylesheet, Editor};
-use terminil::input::{Event, Input, SpecialKey};
+use args::{Action, Args, FileArg, ScriptPart};
+use editor::{config::Config, style::{self, Stylesheet}, Editor, File, Source};
 
 mod terminil;
 mod editor;
+mod args;
 
-fn main() -> Result<(),u8> {
-	if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
-		eprintln!("Stdin and Stdout must be a terminal.");
-		return Err(1);
+///Reads and parses a TOML file, describing any failure in a way that can be shown to the user.
+fn load_toml<T: serde::de::DeserializeOwned>(path: &str) -> Result<T, String> {
+	let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+	toml::from_str(&text).map_err(|e| format!("Could not parse {path}: {e}"))
+}
+
+///Returns where the buffer for a file argument comes from. A path of `-` stands for stdin.
+fn source_for(file: &FileArg, readonly: bool, stdin_data: &Option<Vec<u8>>) -> Source {
+	if file.path == "-" {
+		Source::Scratch(stdin_data.clone().unwrap_or_default())
+	} else {
+		Source::File(File::open(&file.path, readonly))
+	}
+}
+
+///Runs the batch mode script on the files in `args`, without touching the terminal.
+fn run_batch(args: &Args, stdin_data: Option<Vec<u8>>, config: Config) -> ExitCode {
+	let mut editor = Editor::headless();
+	editor.set_config(config);
+	for file in &args.files {
+		if let Err(e) = editor.open(source_for(file, args.readonly, &stdin_data), file.position) {
+			eprintln!("Could not open {}: {e}", file.path);
+			return ExitCode::FAILURE;
+		}
+	}
+	if args.files.is_empty() {
+		editor.open(Source::Scratch(stdin_data.unwrap_or_default()), None).expect("scratch buffers can always be opened");
+	}
+
+	for part in &args.script {
+		let (text, is_file) = match part {
+			ScriptPart::Command(command) => (command.clone(), false),
+			ScriptPart::File(path) => match std::fs::read_to_string(path) {
+				Ok(text) => (text, true),
+				Err(e) => {
+					eprintln!("Could not read {path}: {e}");
+					return ExitCode::FAILURE;
+				},
+			},
+		};
+
+		for (i, line) in text.lines().enumerate() {
+			if is_file && (line.trim().is_empty() || line.trim_start().starts_with('#')) {
+				continue;
+			}
+			match editor.run_command(line) {
+				Ok(ControlFlow::Continue(())) => {},
+				Ok(ControlFlow::Break(())) => return ExitCode::SUCCESS,
+				Err(e) => {
+					match part {
+						ScriptPart::File(path) => eprintln!("{path}:{}: {e}", i + 1),
+						ScriptPart::Command(_) => eprintln!("-c {line:?}: {e}"),
+					}
+					return ExitCode::FAILURE;
+				},
+			}
+		}
 	}
 
-	let styles = match toml::from_str::<Stylesheet>(include_str!("../theme.toml")) {
-		Ok(t) => t,
+	ExitCode::SUCCESS
+}
+
+fn main() -> ExitCode {
+	let args = match args::parse(std::env::args().skip(1), args::path_exists) {
+		Ok(Action::Edit(args)) => args,
+		Ok(Action::Help) => {
+			println!("{}", args::USAGE);
+			return ExitCode::SUCCESS;
+		},
+		Ok(Action::Version) => {
+			println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+			return ExitCode::SUCCESS;
+		},
 		Err(e) => {
-			panic!("{}", e);
+			eprintln!("{e}\n\n{}", args::USAGE);
+			return ExitCode::from(2);
+		},
+	};
+
+	let batch = !args.script.is_empty();
+	//The terminal is used directly, leaving stdin and stdout free to be pipes.
+	if !batch && OpenOptions::new().read(true).write(true).open("/dev/tty").is_err() {
+		eprintln!("There is no terminal to run in.");
+		return ExitCode::FAILURE;
+	}
+
+	let reads_stdin = args.filter || args.files.iter().any(|f| f.path == "-") || (args.files.is_empty() && !std::io::stdin().is_terminal());
+	let mut stdin_data = None;
+	if reads_stdin {
+		let mut data = Vec::new();
+		if let Err(e) = std::io::stdin().read_to_end(&mut data) {
+			eprintln!("Could not read stdin: {e}");
+			return ExitCode::FAILURE;
 		}
+		stdin_data = Some(data);
+	}
+
+	//This is loaded before the terminal is taken over, so that errors can be printed normally.
+	let config = match args.config.as_deref().map_or(Ok(Config::default()), load_toml::<Config>) {
+		Ok(config) => config,
+		Err(e) => {
+			eprintln!("{e}");
+			return ExitCode::FAILURE;
+		},
+	};
+
+	if batch {
+		return run_batch(&args, stdin_data, config);
+	}
+
+	//A broken theme isn't worth refusing to start over, so it's reported once the editor is up.
+	let theme = args.theme.as_deref().or(config.theme.as_deref()).unwrap_or(style::DEFAULT_THEME);
+	let (styles, theme_error) = match Stylesheet::load(theme) {
+		Ok(styles) => (styles, None),
+		Err(e) => (Stylesheet::default(), Some(e)),
 	};
-	
+
 	let mut editor = Editor::new();
 	editor.set_style(styles);
-	printnl!("{:?}", editor.open(editor::Source::File(editor::File::open("test.txt", false))));
+	editor.set_config(config);
+	if let Some(e) = theme_error {
+		editor.show_message(e);
+	}
+
+	let mut opened = 0;
+	for file in &args.files {
+		match editor.open(source_for(file, args.readonly, &stdin_data), file.position) {
+			Ok(()) => opened += 1,
+			Err(e) => editor.show_message(format!("Could not open {}: {e}", file.path)),
+		}
+	}
+	if args.filter {
+		editor.open(Source::Filter(stdin_data.unwrap_or_default()), None).expect("filter buffers can always be opened");
+	} else if opened == 0 {
+		editor.open(Source::Scratch(stdin_data.unwrap_or_default()), None).expect("scratch buffers can always be opened");
+	}
 	editor.run();
+	send!("Exited.\n");
 
-	println!("Exited.");
-	Ok(())
+	if !args.filter {
+		return ExitCode::SUCCESS;
+	}
+	let output = editor.filter_output().map(|rope| rope.bytes_in(0..rope.len()));
+	//Give the terminal back before writing, since whatever reads the output may want it next.
+	drop(editor);
+	match output {
+		Some(output) => match std::io::stdout().write_all(&output).and_then(|()| std::io::stdout().flush()) {
+			Ok(()) => ExitCode::SUCCESS,
+			Err(e) => {
+				eprintln!("Could not write to stdout: {e}");
+				ExitCode::FAILURE
+			},
+		},
+		None => ExitCode::FAILURE,
+	}
 }
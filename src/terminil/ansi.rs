@@ -1,8 +1,11 @@
+use std::{fs::{File, OpenOptions}, io::{stdout, Write}, sync::OnceLock};
+
 
 pub mod consts {
 	use const_format::formatcp;
 	pub const ESC: &str = "\x1b";
 	pub const CSI: &str = formatcp!("{ESC}[");
+	#[allow(unused)]
 	pub const RESET: &str = formatcp!("{CSI}0m");
 }
 
@@ -16,29 +19,42 @@ pub trait ToAnsi {
 // 	}
 // }
 
+#[cfg(test)]
 ///Returns a duplicate string with all ANSI escape codes stripped.
 pub fn strip(text: &str) -> String {
 	let r = regex::Regex::new(r"[\u001b\u009b][\[()#;?]*(?:[0-9]{1,4}(?:;[0-9]{0,4})*)?[0-9A-ORZcf-nqry=><]").expect("this to be a valid regex");
 	r.replace_all(text, "").to_string()
 }
 
-///This macro acts like `print!` but it flushes stdout afterward, which forces the data through.
-///Calling it with no parameters only flushes stdout.
+///Returns a writer for the terminal. This is the controlling terminal rather than stdout, which could be a pipe
+///carrying the editor's output (as in filter mode). Without a controlling terminal, stdout is used instead.
+pub fn output() -> Box<dyn Write> {
+	static TTY: OnceLock<Option<File>> = OnceLock::new();
+	match TTY.get_or_init(|| OpenOptions::new().write(true).open("/dev/tty").ok()) {
+		Some(tty) => Box::new(tty),
+		None => Box::new(stdout()),
+	}
+}
+
+///This macro acts like `print!` but it writes to the terminal (see [output]) in a single call, which forces the data
+///through. Calling it with no parameters only flushes the terminal.
 #[macro_export]
 macro_rules! send {
 	($fmt:literal) => {{
-		use std::io::{stdout, Write};
-		print!($fmt);
-		let _ = stdout().flush();
+		use std::io::Write;
+		let mut out = $crate::terminil::ansi::output();
+		let _ = out.write_all(format!($fmt).as_bytes());
+		let _ = out.flush();
 	}};
 	($fmt:literal,$($args:expr),*) => {{
-		use std::io::{stdout, Write};
-		print!($fmt, $($args),*);
-		let _ = stdout().flush();
+		use std::io::Write;
+		let mut out = $crate::terminil::ansi::output();
+		let _ = out.write_all(format!($fmt, $($args),*).as_bytes());
+		let _ = out.flush();
 	}};
 	() => {{
-		use std::io::{stdout, Write};
-		let _ = stdout().flush();
+		use std::io::Write;
+		let _ = $crate::terminil::ansi::output().flush();
 	}}
 }
 
@@ -46,11 +62,9 @@ macro_rules! send {
 #[macro_export]
 macro_rules! printnl {
 	($fmt:literal) => {{
-		print!($fmt);
-		send!("\n\x1B[G");
+		send!("{}\n\x1B[G", format!($fmt));
 	}};
 	($fmt:literal,$($args:expr),*) => {{
-		print!($fmt, $($args),*);
-		send!("\n\x1B[G");
+		send!("{}\n\x1B[G", format!($fmt, $($args),*));
 	}};
 }
\ No newline at end of file
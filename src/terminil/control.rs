@@ -1,8 +1,9 @@
 use super::ansi::{consts::*, ToAnsi};
 
 #[allow(unused)]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum Motion {
+	#[default]
 	None,
 	Up,
 	Down,
@@ -27,7 +28,7 @@ pub enum Motion {
 } impl ToAnsi for Motion {
 	fn to_ansi(&self) -> String {
 		match self {
-			Motion::None  => format!(""),
+			Motion::None  => String::new(),
 			Motion::Up    => format!("{CSI}A"),
 			Motion::Down  => format!("{CSI}B"),
 			Motion::Left  => format!("{CSI}C"),
@@ -58,10 +59,6 @@ pub enum Motion {
 			Motion::ScrollDown => format!("{CSI}T"),
 		}
 	}
-} impl Default for Motion {
-	fn default() -> Self {
-		Motion::None
-	}
 }
 
 #[allow(unused)]
@@ -81,6 +78,20 @@ pub enum Action {
 	AlternateBuffer(bool),
 	BracketPaste(bool),
 	FocusReport(bool),
+	///Reports mouse button presses and releases, and wheel scrolling.
+	MouseClicks(bool),
+	///Reports mouse motion while a button is held, along with everything `MouseClicks` reports.
+	MouseDrags(bool),
+	///Reports all mouse motion, along with everything `MouseClicks` reports.
+	MouseMotion(bool),
+	///Encodes mouse reports as SGR (1006) sequences, which have no limit on coordinates.
+	SgrMouse(bool),
+	///Pushes a set of kitty keyboard protocol enhancement flags onto the terminal's stack.
+	PushKeyboardFlags(u8),
+	///Restores the kitty keyboard protocol flags which were in use before the last push.
+	PopKeyboardFlags,
+	///Asks the terminal for its current kitty keyboard protocol flags. Terminals without the protocol don't reply.
+	QueryKeyboardFlags,
 } impl ToAnsi for Action {
 	fn to_ansi(&self) -> String {
 		match self {
@@ -98,6 +109,14 @@ pub enum Action {
 			Action::AlternateBuffer(b) => if *b { format!("{CSI}?1049h") } else { format!("{CSI}?1049l") },
 			Action::BracketPaste(b)    => if *b { format!("{CSI}?2004h") } else { format!("{CSI}?2004l") },
 			Action::FocusReport(r)     => if *r { format!("{CSI}?1004h") } else { format!("{CSI}?1004l") },
+			Action::MouseClicks(m)     => if *m { format!("{CSI}?1000h") } else { format!("{CSI}?1000l") },
+			Action::MouseDrags(m)      => if *m { format!("{CSI}?1002h") } else { format!("{CSI}?1002l") },
+			Action::MouseMotion(m)     => if *m { format!("{CSI}?1003h") } else { format!("{CSI}?1003l") },
+			Action::SgrMouse(m)        => if *m { format!("{CSI}?1006h") } else { format!("{CSI}?1006l") },
+
+			Action::PushKeyboardFlags(flags) => format!("{CSI}>{flags}u"),
+			Action::PopKeyboardFlags         => format!("{CSI}<u"),
+			Action::QueryKeyboardFlags       => format!("{CSI}?u"),
 		}
 	}
 }
\ No newline at end of file
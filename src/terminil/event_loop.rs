@@ -0,0 +1,219 @@
+//! Waits on terminal input, signals, timers and messages from other threads all at once.
+
+use std::{io::Error, os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd}, sync::{mpsc, Arc}, time::{Duration, Instant}};
+
+use super::input::{Event, Input, Wait};
+
+///Something which happened while waiting in [EventLoop::next].
+#[derive(Debug)]
+pub enum LoopEvent<T> {
+	Input(Event),
+	///A timer set with [EventLoop::set_timer] went off.
+	Timer(T),
+	///A message sent through a [Waker].
+	Message(T),
+}
+
+///Identifies a timer, so that it can be cancelled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimerId(u64);
+
+struct Timer<T> {
+	id: TimerId,
+	deadline: Instant,
+	message: T,
+}
+
+///The timers of an [EventLoop], kept apart from the terminal so they can be tested.
+struct Timers<T> {
+	timers: Vec<Timer<T>>,
+	next_id: u64,
+} impl<T> Timers<T> {
+	fn new() -> Self {
+		Timers { timers: Vec::new(), next_id: 0 }
+	}
+	fn set(&mut self, deadline: Instant, message: T) -> TimerId {
+		let id = TimerId(self.next_id);
+		self.next_id += 1;
+		self.timers.push(Timer { id, deadline, message });
+
+		id
+	}
+	fn cancel(&mut self, id: TimerId) {
+		self.timers.retain(|t| t.id != id);
+	}
+	///Removes the timer with the earliest deadline if it has passed by `now`, returning its message. Timers with the
+	///same deadline go off in the order they were set.
+	fn expired(&mut self, now: Instant) -> Option<T> {
+		let (i, _) = self.timers.iter().enumerate()
+			.filter(|(_, t)| t.deadline <= now)
+			.min_by_key(|(_, t)| t.deadline)?;
+		Some(self.timers.remove(i).message)
+	}
+	fn next_deadline(&self) -> Option<Instant> {
+		self.timers.iter().map(|t| t.deadline).min()
+	}
+}
+
+///Sends messages to an [EventLoop] from any thread, waking it up if it is waiting.
+pub struct Waker<T> {
+	sender: mpsc::Sender<T>,
+	wake: Arc<OwnedFd>,
+} impl<T> Waker<T> {
+	///Sends a message to the event loop. Fails once the event loop has been dropped.
+	pub fn send(&self, message: T) -> Result<(), mpsc::SendError<T>> {
+		self.sender.send(message)?;
+
+		//If the pipe is full, the loop is due to wake up anyway.
+		let byte = 0u8;
+		unsafe { libc::write(self.wake.as_raw_fd(), &byte as *const u8 as *const libc::c_void, 1) };
+
+		Ok(())
+	}
+}
+impl<T> Clone for Waker<T> {
+	fn clone(&self) -> Self {
+		Waker { sender: self.sender.clone(), wake: self.wake.clone() }
+	}
+}
+
+///The receiving end of the messages sent through [Waker]s, along with a pipe which becomes readable when one arrives.
+struct Mailbox<T> {
+	sender: mpsc::Sender<T>,
+	receiver: mpsc::Receiver<T>,
+	wake_read: OwnedFd,
+	wake_write: Arc<OwnedFd>,
+} impl<T> Mailbox<T> {
+	fn new() -> Result<Self, Error> {
+		let mut fds = [0; 2];
+		if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } != 0 {
+			return Err(Error::last_os_error());
+		}
+		let (wake_read, wake_write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+		let (sender, receiver) = mpsc::channel();
+
+		Ok(Mailbox { sender, receiver, wake_read, wake_write: Arc::new(wake_write) })
+	}
+	fn waker(&self) -> Waker<T> {
+		Waker { sender: self.sender.clone(), wake: self.wake_write.clone() }
+	}
+	///Returns the file descriptor to wait on for messages.
+	fn fd(&self) -> RawFd {
+		self.wake_read.as_raw_fd()
+	}
+	fn try_recv(&self) -> Option<T> {
+		self.receiver.try_recv().ok()
+	}
+	///Empties the wake pipe, once it has woken the loop up.
+	fn drain(&self) {
+		let mut data = [0u8; 64];
+		while unsafe { libc::read(self.wake_read.as_raw_fd(), data.as_mut_ptr() as *mut libc::c_void, data.len()) } > 0 {}
+	}
+}
+
+pub struct EventLoop<T> {
+	input: Input,
+	timers: Timers<T>,
+	mailbox: Mailbox<T>,
+} impl<T> EventLoop<T> {
+	pub fn new(input: Input) -> Result<Self, Error> {
+		Ok(EventLoop { input, timers: Timers::new(), mailbox: Mailbox::new()? })
+	}
+	pub fn input(&self) -> &Input {
+		&self.input
+	}
+	pub fn input_mut(&mut self) -> &mut Input {
+		&mut self.input
+	}
+	///Returns a handle which worker threads can use to send messages to the loop.
+	pub fn waker(&self) -> Waker<T> {
+		self.mailbox.waker()
+	}
+	///Makes [EventLoop::next] return `message` once `delay` has passed.
+	pub fn set_timer(&mut self, delay: Duration, message: T) -> TimerId {
+		self.timers.set(Instant::now() + delay, message)
+	}
+	///Stops a timer from going off. Does nothing if it has already gone off.
+	pub fn cancel_timer(&mut self, id: TimerId) {
+		self.timers.cancel(id);
+	}
+	///Waits for the next event from any source. Returns `None` once the terminal input has ended.
+	pub fn next(&mut self) -> Option<LoopEvent<T>> {
+		loop {
+			if let Some(message) = self.mailbox.try_recv() {
+				return Some(LoopEvent::Message(message));
+			}
+			if let Some(message) = self.timers.expired(Instant::now()) {
+				return Some(LoopEvent::Timer(message));
+			}
+
+			let wake = self.mailbox.fd();
+			match self.input.wait(self.timers.next_deadline(), &[wake]) {
+				Wait::Event(event) => return Some(LoopEvent::Input(event)),
+				Wait::Ready(fds) => if fds.contains(&wake) {
+					self.mailbox.drain();
+				},
+				Wait::Timeout => {},
+				Wait::Closed => return None,
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::time::{Duration, Instant};
+
+	use super::{Mailbox, Timers};
+	use crate::terminil::input::wait_readable;
+
+	#[test]
+	fn timers() {
+		let now = Instant::now();
+		let mut timers = Timers::new();
+		timers.set(now + Duration::from_secs(3), "last");
+		timers.set(now + Duration::from_secs(1), "first");
+		let cancelled = timers.set(now + Duration::from_secs(1), "cancelled");
+		timers.set(now + Duration::from_secs(2), "second");
+		timers.set(now + Duration::from_secs(2), "also second");
+		timers.cancel(cancelled);
+
+		assert_eq!(timers.next_deadline(), Some(now + Duration::from_secs(1)));
+		assert_eq!(timers.expired(now), None);
+
+		//Once several have gone off, the earliest comes out first no matter when it was set.
+		let later = now + Duration::from_secs(5);
+		assert_eq!(timers.expired(later), Some("first"));
+		assert_eq!(timers.expired(later), Some("second"));
+		assert_eq!(timers.expired(later), Some("also second"));
+		assert_eq!(timers.expired(later), Some("last"));
+		assert_eq!(timers.expired(later), None);
+		assert_eq!(timers.next_deadline(), None);
+	}
+
+	#[test]
+	fn waker_wakes() {
+		let mailbox = Mailbox::new().unwrap();
+		assert_eq!(wait_readable(&[mailbox.fd()], Some(Duration::ZERO)), [false]);
+
+		let waker = mailbox.waker();
+		let thread = std::thread::spawn(move || {
+			std::thread::sleep(Duration::from_millis(50));
+			waker.send(1).unwrap();
+			waker.send(2).unwrap();
+		});
+		assert_eq!(wait_readable(&[mailbox.fd()], Some(Duration::from_secs(5))), [true]);
+		thread.join().unwrap();
+
+		mailbox.drain();
+		assert_eq!(wait_readable(&[mailbox.fd()], Some(Duration::ZERO)), [false]);
+		assert_eq!(mailbox.try_recv(), Some(1));
+		assert_eq!(mailbox.try_recv(), Some(2));
+		assert_eq!(mailbox.try_recv(), None);
+
+		//Sending fails once the loop is gone.
+		let waker = mailbox.waker();
+		drop(mailbox);
+		assert!(waker.send(3).is_err());
+	}
+}
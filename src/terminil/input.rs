@@ -1,10 +1,10 @@
-use std::{io::{stdin, ErrorKind, Read}, mem};
+use std::{fs::File, io::{ErrorKind, Read}, os::fd::{AsRawFd, RawFd}, time::{Duration, Instant}};
 
 use terminal_utils::RawModeGuard;
 
-use crate::{printnl, send, terminil::{ansi::ToAnsi, control::Action}};
+use crate::{send, terminil::{ansi::ToAnsi, control::Action, signal::SignalPipe}};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SpecialKey {
 	Escape,
 	Backspace,
@@ -21,19 +21,447 @@ pub enum SpecialKey {
 	Fn(u8),
 }
 
-#[derive(Debug, Clone)]
+///A set of modifier keys held down along with a key. Shift isn't reported for keys which produce text, since it is
+///already reflected in the character (e.g. `A` rather than `a`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers(u8);
+impl Modifiers {
+	pub const NONE: Modifiers = Modifiers(0);
+	pub const SHIFT: Modifiers = Modifiers(1);
+	pub const ALT: Modifiers = Modifiers(2);
+	pub const CTRL: Modifiers = Modifiers(4);
+	pub const SUPER: Modifiers = Modifiers(8);
+
+	///Decodes the modifier parameter of a key sequence, which is one more than a bitmask of the modifiers.
+	fn from_param(param: u32) -> Self {
+		Modifiers((param.saturating_sub(1) & 0xF) as u8)
+	}
+	pub fn contains(self, other: Modifiers) -> bool {
+		self.0 & other.0 == other.0
+	}
+}
+impl std::ops::BitOr for Modifiers {
+	type Output = Modifiers;
+	fn bitor(self, rhs: Modifiers) -> Modifiers {
+		Modifiers(self.0 | rhs.0)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+	///A key which produces text. Enter and Tab are `\n` and `\t`.
+	Char(char),
+	Special(SpecialKey),
+}
+
+///Whether a key was pressed or released. Only terminals with the kitty keyboard protocol report repeats and releases;
+///everywhere else, every key event is a press.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum KeyKind {
+	#[default]
+	Press,
+	Repeat,
+	Release,
+}
+
+///A key press, along with the modifiers held down during it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEvent {
+	pub key: Key,
+	pub modifiers: Modifiers,
+	pub kind: KeyKind,
+} impl KeyEvent {
+	pub fn new(key: Key, modifiers: Modifiers) -> Self {
+		KeyEvent { key, modifiers, kind: KeyKind::Press }
+	}
+	fn with(self, modifiers: Modifiers) -> Self {
+		KeyEvent { modifiers: self.modifiers | modifiers, ..self }
+	}
+}
+impl From<char> for KeyEvent {
+	fn from(c: char) -> Self {
+		KeyEvent::new(Key::Char(c), Modifiers::NONE)
+	}
+}
+impl From<SpecialKey> for KeyEvent {
+	fn from(key: SpecialKey) -> Self {
+		KeyEvent::new(Key::Special(key), Modifiers::NONE)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MouseButton {
+	Left,
+	Middle,
+	Right,
+	///Extra buttons, numbered like X11 does. Buttons 4 to 7 are the wheel, so these start from 8.
+	Other(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MouseKind {
+	Press(MouseButton),
+	Release(MouseButton),
+	///Motion while a button is held.
+	Drag(MouseButton),
+	///Motion with no buttons held.
+	Move,
+	ScrollUp,
+	ScrollDown,
+	ScrollLeft,
+	ScrollRight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MouseEvent {
+	pub kind: MouseKind,
+	pub modifiers: Modifiers,
+	///The zero-indexed column of the cell under the pointer.
+	pub x: u16,
+	///The zero-indexed row of the cell under the pointer.
+	pub y: u16,
+}
+
+///Replies to queries sent to the terminal. [Input] handles these itself rather than returning them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+	///The terminal's current kitty keyboard protocol flags.
+	KeyboardFlags(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Event {
-	///A single character of valid unicode (e.g. a UTF-8 sequence). Should only contain printable characters.
-	TextChar(char),
-	///A single character that isn't valid unicode (e.g. ASCII past 127)
+	Key(KeyEvent),
+	///A single byte which isn't part of any valid key (e.g. a stray UTF-8 continuation byte).
 	Byte(u8),
 	Paste(Vec<u8>),
 	Focus(bool),
-	SpecialKey(SpecialKey),
+	Mouse(MouseEvent),
+	///The terminal was resized to `(width, height)`.
+	Resize(u16, u16),
+	Reply(Reply),
 
 	Unknown,
 }
 
+///How long to wait for the rest of an escape sequence before deciding that it was a lone press of the Escape key.
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(25);
+
+///The kitty keyboard protocol enhancements to use: disambiguating escape codes (1) and reporting key releases (2).
+const KEYBOARD_FLAGS: u8 = 0b11;
+
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+///The result of parsing the start of the input queue.
+#[derive(Debug, PartialEq)]
+enum Parsed {
+	///An event, along with how many bytes it was made from.
+	Complete(Event, usize),
+	///The queue ends partway through an event.
+	Incomplete,
+}
+
+fn key(key: impl Into<KeyEvent>) -> Event {
+	Event::Key(key.into())
+}
+
+///Parses one event from the start of `data`, which must not be empty. Bracketed pastes are handled by [Parser].
+fn parse(data: &[u8]) -> Parsed {
+	use Parsed::*;
+
+	match data[0] {
+		0x1B => match data.get(1) {
+			None => Incomplete,
+			Some(b'[') => parse_csi(data),
+			Some(b'O') => match data.get(2) {
+				None => Incomplete,
+				Some(&code) => match legacy_key(code) {
+					Some(key) => Complete(Event::Key(key.into()), 3),
+					None => Complete(Event::Unknown, 3),
+				},
+			},
+			//A second escape starts a new sequence, so the first one was pressed on its own.
+			Some(0x1B) => Complete(key(SpecialKey::Escape), 1),
+			//Escape followed by a key is how terminals send Alt chords.
+			Some(_) => match parse(&data[1..]) {
+				Complete(Event::Key(key), len) => Complete(Event::Key(key.with(Modifiers::ALT)), len + 1),
+				Complete(_, len) => Complete(Event::Unknown, len + 1),
+				Incomplete => Incomplete,
+			},
+		},
+		//Many terminals send ^H for Backspace rather than DEL.
+		8 | 127 => Complete(key(SpecialKey::Backspace), 1),
+		9 => Complete(key('\t'), 1),
+		13 => Complete(key('\n'), 1),
+		0 => Complete(Event::Key(KeyEvent::new(Key::Char(' '), Modifiers::CTRL)), 1),
+		byte @ 1..=26 => Complete(Event::Key(KeyEvent::new(Key::Char((b'a' + byte - 1) as char), Modifiers::CTRL)), 1),
+		byte @ 28..=31 => Complete(Event::Key(KeyEvent::new(Key::Char((byte + 0x40) as char), Modifiers::CTRL)), 1),
+		byte @ 32..=126 => Complete(key(byte as char), 1),
+		lead => {
+			let len = match lead {
+				0xC2..=0xDF => 2,
+				0xE0..=0xEF => 3,
+				0xF0..=0xF4 => 4,
+				_ => return Complete(Event::Byte(lead), 1),
+			};
+			if data.len() < len {
+				//Bail out early on bytes which can't continue the sequence, rather than waiting for more.
+				return if data[1..].iter().all(|b| b & 0xC0 == 0x80) { Incomplete } else { Complete(Event::Byte(lead), 1) };
+			}
+			match std::str::from_utf8(&data[..len]) {
+				Ok(text) => Complete(key(text.chars().next().expect("this to be one character")), len),
+				Err(_) => Complete(Event::Byte(lead), 1),
+			}
+		},
+	}
+}
+
+///Returns the key for the final byte of sequences like `ESC [ A` or `ESC O P`.
+fn legacy_key(code: u8) -> Option<SpecialKey> {
+	match code {
+		b'A' => Some(SpecialKey::Up),
+		b'B' => Some(SpecialKey::Down),
+		b'C' => Some(SpecialKey::Right),
+		b'D' => Some(SpecialKey::Left),
+		b'H' => Some(SpecialKey::Home),
+		b'F' => Some(SpecialKey::End),
+		b'P' => Some(SpecialKey::Fn(1)),
+		b'Q' => Some(SpecialKey::Fn(2)),
+		b'R' => Some(SpecialKey::Fn(3)),
+		b'S' => Some(SpecialKey::Fn(4)),
+		_ => None,
+	}
+}
+
+///Returns the key for the number in sequences like `ESC [ 3 ~`.
+fn tilde_key(number: u32) -> Option<SpecialKey> {
+	match number {
+		1 | 7 => Some(SpecialKey::Home),
+		2 => Some(SpecialKey::Insert),
+		3 => Some(SpecialKey::Delete),
+		4 | 8 => Some(SpecialKey::End),
+		5 => Some(SpecialKey::PgUp),
+		6 => Some(SpecialKey::PgDn),
+		11..=15 => Some(SpecialKey::Fn((number - 10) as u8)),
+		17..=21 => Some(SpecialKey::Fn((number - 11) as u8)),
+		23..=26 => Some(SpecialKey::Fn((number - 12) as u8)),
+		28..=29 => Some(SpecialKey::Fn((number - 13) as u8)),
+		31..=34 => Some(SpecialKey::Fn((number - 14) as u8)),
+		_ => None,
+	}
+}
+
+///Parses a control sequence (`ESC [ parameters final`) from the start of `data`.
+fn parse_csi(data: &[u8]) -> Parsed {
+	use Parsed::*;
+
+	let Some(end) = data.iter().skip(2).position(|b| !(0x20..=0x3F).contains(b)).map(|i| i + 2) else {
+		return Incomplete;
+	};
+	let len = end + 1;
+	if !(0x40..=0x7E).contains(&data[end]) {
+		//Not a valid sequence, so drop what has been seen of it.
+		return Complete(Event::Unknown, end);
+	}
+
+	//Parameters are numbers separated by semicolons, which can be split further by colons. A leading `<`, `=`, `>` or
+	//`?` marks sequences which aren't standard.
+	let (prefix, params) = match data[2] {
+		prefix @ (b'<' | b'=' | b'>' | b'?') => (Some(prefix), &data[3..end]),
+		_ => (None, &data[2..end]),
+	};
+	if !params.iter().all(|b| b.is_ascii_digit() || *b == b';' || *b == b':') {
+		return Complete(Event::Unknown, len);
+	}
+	let params: Vec<Vec<u32>> = params.split(|b| *b == b';').map(|param| {
+		param.split(|b| *b == b':').map(|p| std::str::from_utf8(p).ok().and_then(|p| p.parse().ok()).unwrap_or(0)).collect()
+	}).collect();
+	//Missing parameters are zero.
+	let param = |i: usize, j: usize| params.get(i).and_then(|p| p.get(j)).copied().unwrap_or(0);
+
+	match (prefix, data[end]) {
+		(Some(b'?'), b'u') => return Complete(Event::Reply(Reply::KeyboardFlags(param(0, 0))), len),
+		(Some(b'<'), b'M' | b'm') => return match parse_mouse(param(0, 0), param(1, 0), param(2, 0), data[end] == b'm') {
+			Some(mouse) => Complete(Event::Mouse(mouse), len),
+			None => Complete(Event::Unknown, len),
+		},
+		(Some(_), _) => return Complete(Event::Unknown, len),
+		(None, b'I') if params == [[0]] => return Complete(Event::Focus(true), len),
+		(None, b'O') if params == [[0]] => return Complete(Event::Focus(false), len),
+		_ => {},
+	}
+
+	let key = match data[end] {
+		b'u' => kitty_key(param(0, 0)),
+		b'Z' => Some(KeyEvent::new(Key::Char('\t'), Modifiers::SHIFT)),
+		b'~' => tilde_key(param(0, 0)).map(KeyEvent::from),
+		code => legacy_key(code).map(KeyEvent::from),
+	};
+	let Some(mut key) = key else {
+		return Complete(Event::Unknown, len);
+	};
+
+	key = key.with(Modifiers::from_param(param(1, 0)));
+	key.kind = match param(1, 1) {
+		2 => KeyKind::Repeat,
+		3 => KeyKind::Release,
+		_ => KeyKind::Press,
+	};
+	//Keep Shift out of text keys, like in legacy input. `shifted` is the key's shifted form, if the terminal sent it.
+	if let Key::Char(c) = key.key {
+		if key.modifiers.contains(Modifiers::SHIFT) && !c.is_control() {
+			let shifted = char::from_u32(param(0, 1)).filter(|_| param(0, 1) != 0);
+			let mut upper = c.to_uppercase();
+			let shifted = shifted.or(if upper.len() == 1 { upper.next() } else { None });
+			if let Some(shifted) = shifted {
+				key.key = Key::Char(shifted);
+				key.modifiers = Modifiers(key.modifiers.0 & !Modifiers::SHIFT.0);
+			}
+		}
+	}
+
+	Complete(Event::Key(key), len)
+}
+
+///Decodes the parameters of an SGR mouse report (`ESC [ < button ; x ; y M`, or `m` for releases).
+fn parse_mouse(code: u32, x: u32, y: u32, release: bool) -> Option<MouseEvent> {
+	//The low bits hold the button, with bit 6 (wheel) and bit 7 (extra buttons) selecting between banks of buttons.
+	let button = match (code & 0b11, code & 0b1100_0000) {
+		(0, 0) => Some(MouseButton::Left),
+		(1, 0) => Some(MouseButton::Middle),
+		(2, 0) => Some(MouseButton::Right),
+		(3, 0) => None,
+		(n, 0b1000_0000) => Some(MouseButton::Other(n as u8 + 8)),
+		_ => None,
+	};
+
+	let kind = if code & 64 != 0 && code & 128 == 0 {
+		match code & 0b11 {
+			0 => MouseKind::ScrollUp,
+			1 => MouseKind::ScrollDown,
+			2 => MouseKind::ScrollLeft,
+			_ => MouseKind::ScrollRight,
+		}
+	} else if code & 32 != 0 {
+		button.map_or(MouseKind::Move, MouseKind::Drag)
+	} else if release {
+		MouseKind::Release(button?)
+	} else {
+		MouseKind::Press(button?)
+	};
+
+	let mut modifiers = Modifiers::NONE;
+	for (bit, modifier) in [(4, Modifiers::SHIFT), (8, Modifiers::ALT), (16, Modifiers::CTRL)] {
+		if code & bit != 0 {
+			modifiers = modifiers | modifier;
+		}
+	}
+
+	Some(MouseEvent {
+		kind,
+		modifiers,
+		x: x.saturating_sub(1).min(u16::MAX as u32) as u16,
+		y: y.saturating_sub(1).min(u16::MAX as u32) as u16,
+	})
+}
+
+///Returns the key for a kitty keyboard protocol key code (`ESC [ code u`), which is mostly a Unicode code point.
+fn kitty_key(code: u32) -> Option<KeyEvent> {
+	match code {
+		9 => Some('\t'.into()),
+		13 => Some('\n'.into()),
+		27 => Some(SpecialKey::Escape.into()),
+		127 => Some(SpecialKey::Backspace.into()),
+		//The private use area holds keys like keypad keys and lone modifiers, which aren't handled.
+		0xE000..=0xF8FF => None,
+		code => char::from_u32(code).map(KeyEvent::from),
+	}
+}
+
+///Turns a stream of bytes from the terminal into [Event]s. Bytes can be fed in pieces of any size: events split across
+///several pieces are put back together, and a piece holding several events produces all of them.
+#[derive(Debug, Default)]
+pub struct Parser {
+	queue: Vec<u8>,
+	///How much of a bracketed paste at the start of the queue has been searched for its end already.
+	paste_searched: usize,
+} impl Parser {
+	pub fn feed(&mut self, data: &[u8]) {
+		self.queue.extend_from_slice(data);
+	}
+	///Returns true if there are bytes waiting which don't make up a whole event yet, and which [Parser::flush] would
+	///turn into an event if no more input arrives.
+	pub fn is_pending(&self) -> bool {
+		!self.queue.is_empty() && !self.queue.starts_with(PASTE_START)
+	}
+	///Takes the next complete event out of the queue.
+	pub fn next_event(&mut self) -> Option<Event> {
+		if self.queue.is_empty() {
+			return None;
+		}
+
+		let (event, len) = if self.queue.starts_with(PASTE_START) {
+			let from = self.paste_searched.max(PASTE_START.len());
+			match self.queue[from..].windows(PASTE_END.len()).position(|w| w == PASTE_END) {
+				Some(i) => {
+					let end = from + i;
+					(Event::Paste(self.queue[PASTE_START.len()..end].to_vec()), end + PASTE_END.len())
+				},
+				None => {
+					//The end marker could be split across reads, so part of it may already be here.
+					self.paste_searched = self.queue.len().saturating_sub(PASTE_END.len() - 1);
+					return None;
+				},
+			}
+		} else {
+			match parse(&self.queue) {
+				Parsed::Complete(event, len) => (event, len),
+				Parsed::Incomplete => return None,
+			}
+		};
+
+		self.queue.drain(..len);
+		self.paste_searched = 0;
+
+		Some(event)
+	}
+	///Takes the next event out of the queue when no more input has arrived to complete it, which makes a lone escape
+	///byte a press of the Escape key. Bracketed pastes are never cut short.
+	pub fn flush(&mut self) -> Option<Event> {
+		if self.queue.is_empty() || self.queue.starts_with(PASTE_START) {
+			return None;
+		}
+
+		let first = self.queue.remove(0);
+		Some(match first {
+			0x1B => key(SpecialKey::Escape),
+			_ => Event::Byte(first),
+		})
+	}
+}
+
+///Waits until one of `fds` has data to read, or `timeout` passes. Returns which of them have data.
+pub(super) fn wait_readable(fds: &[i32], timeout: Option<Duration>) -> Vec<bool> {
+	let mut polls: Vec<libc::pollfd> = fds.iter().map(|&fd| libc::pollfd { fd, events: libc::POLLIN, revents: 0 }).collect();
+	let timeout = timeout.map_or(-1, |t| t.as_millis() as i32);
+	let result = unsafe { libc::poll(polls.as_mut_ptr(), polls.len() as libc::nfds_t, timeout) };
+
+	//Errors (like being interrupted by a signal) are treated like the timeout running out.
+	polls.iter().map(|p| result > 0 && p.revents != 0).collect()
+}
+
+///The outcome of [Input::wait].
+#[derive(Debug)]
+pub enum Wait {
+	Event(Event),
+	///Some of the extra file descriptors which were being waited on became readable.
+	Ready(Vec<RawFd>),
+	Timeout,
+	///The input has ended, so no more events will arrive.
+	Closed,
+}
+
 ///Input handler which can be iterated over to receive Events.
 ///
 ///This struct may be platform-specific.
@@ -42,112 +470,131 @@ pub enum Event {
 ///when the struct is dropped. **The default settings which are applied afterward may not match the values of the settings
 ///before the struct was instantiated.**
 pub struct Input {
-	_guard: RawModeGuard,
+	///Keys are read from the terminal itself rather than stdin, which may be a pipe.
+	tty: File,
+	///Restores the terminal's original mode when dropped. Only `None` while suspended.
+	guard: Option<RawModeGuard>,
+	parser: Parser,
+	///Set once the terminal has replied that it supports the kitty keyboard protocol, and the flags have been pushed.
+	keyboard_flags_pushed: bool,
+	///Signals terminal resizes. Without it, resizes are only noticed on the next redraw.
+	resize: Option<SignalPipe>,
 }
 impl Input {
 	pub fn new() -> Self {
-		println!("Hi");
-		let guard = terminal_utils::enable_raw_mode().expect("to be able to enter raw mode");
-		send!("{}{}{}{}", Action::AlternateBuffer(true).to_ansi(), Action::BracketPaste(true).to_ansi(), Action::FocusReport(true).to_ansi(), Action::EraseScrollback.to_ansi());
+		let mut input = Input {
+			tty: File::open("/dev/tty").expect("to be able to open the terminal"),
+			guard: None,
+			parser: Parser::default(),
+			keyboard_flags_pushed: false,
+			resize: SignalPipe::new(libc::SIGWINCH).ok(),
+		};
+		input.enter();
+		//Only done once, as clearing the scrollback on every resume would wipe out what the user did in the shell.
+		send!("{}", Action::EraseScrollback.to_ansi());
+		//Support for the kitty keyboard protocol is only known once the terminal replies, which it may never do.
+		//Until then (or if it doesn't), keys arrive in the legacy encodings.
+		send!("{}", Action::QueryKeyboardFlags.to_ansi());
 
-		Input {
-			_guard: guard
+		input
+	}
+	///Puts the terminal into raw mode and turns on everything the editor relies on.
+	fn enter(&mut self) {
+		self.guard = Some(terminal_utils::enable_raw_mode().expect("to be able to enter raw mode"));
+		send!("{}{}{}", Action::AlternateBuffer(true).to_ansi(), Action::BracketPaste(true).to_ansi(), Action::FocusReport(true).to_ansi());
+		send!("{}{}", Action::MouseDrags(true).to_ansi(), Action::SgrMouse(true).to_ansi());
+		if self.keyboard_flags_pushed {
+			send!("{}", Action::PushKeyboardFlags(KEYBOARD_FLAGS).to_ansi());
 		}
 	}
-	pub fn get_event(&mut self) -> Option<Event> {
-		let mut data = [0;16];
-
-		match stdin().read(&mut data) {
-			Ok(len) => {
-				let data = mem::take(&mut data);
-				if len == 0 {
-					None
-				} else if data[0] == 0x1B {
-					if len == 1 {
-						Some(Event::SpecialKey(SpecialKey::Escape))
-					} else {
-						match data[1..6] {
-							[b'[',b'2',b'~',..] => Some(Event::SpecialKey(SpecialKey::Insert)),
-							[b'[',b'3',b'~',..] => Some(Event::SpecialKey(SpecialKey::Delete)),
-							[b'[',b'H',..]      => Some(Event::SpecialKey(SpecialKey::Home)),
-							[b'[',b'F',..]      => Some(Event::SpecialKey(SpecialKey::End)),
-							[b'[',b'5',b'~',..] => Some(Event::SpecialKey(SpecialKey::PgUp)),
-							[b'[',b'6',b'~',..] => Some(Event::SpecialKey(SpecialKey::PgDn)),
-							
-							[b'[',b'A',..]      => Some(Event::SpecialKey(SpecialKey::Up)),
-							[b'[',b'B',..]      => Some(Event::SpecialKey(SpecialKey::Down)),
-							[b'[',b'C',..]      => Some(Event::SpecialKey(SpecialKey::Right)),
-							[b'[',b'D',..]      => Some(Event::SpecialKey(SpecialKey::Left)),
-
-							[b'O',b'P',..]                => Some(Event::SpecialKey(SpecialKey::Fn(1))),
-							[b'O',b'Q',..]                => Some(Event::SpecialKey(SpecialKey::Fn(2))),
-							[b'O',b'R',..]                => Some(Event::SpecialKey(SpecialKey::Fn(3))),
-							[b'O',b'S',..]                => Some(Event::SpecialKey(SpecialKey::Fn(4))),
-							[b'[',b'1',b'5',b'~',..]      => Some(Event::SpecialKey(SpecialKey::Fn(5))),
-							[b'[',b'1',b'7',b'~',..]      => Some(Event::SpecialKey(SpecialKey::Fn(6))),
-							[b'[',b'1',b'8',b'~',..]      => Some(Event::SpecialKey(SpecialKey::Fn(7))),
-							[b'[',b'1',b'9',b'~',..]      => Some(Event::SpecialKey(SpecialKey::Fn(8))),
-							[b'[',b'2',b'0',b'~',..]      => Some(Event::SpecialKey(SpecialKey::Fn(9))),
-							[b'[',b'2',b'1',b'~',..]      => Some(Event::SpecialKey(SpecialKey::Fn(10))),
-							[b'[',b'2',b'3',b'~',..]      => Some(Event::SpecialKey(SpecialKey::Fn(11))),
-							[b'[',b'2',b'4',b'~',..]      => Some(Event::SpecialKey(SpecialKey::Fn(12))),
-							
-							[b'[',b'I',..]      => Some(Event::Focus(true)),
-							[b'[',b'O',..]      => Some(Event::Focus(false)),
-
-							[b'[',b'2',b'0',b'0',b'~',..] => { //Paste
-								let mut data = data[6..].to_vec();
-
-								while !data.ends_with(b"\x1b[201~") {
-									let mut next_chunk = [0;128];
-									let len = stdin().read(&mut next_chunk);
-									if let Ok(len) = len {
-										data.extend_from_slice(&next_chunk[0..len]);
-									} else {
-										break;
-									}
-								}
-								//Remove the escape
-								data.truncate(data.len()-6);
-								//Unlikely this will be written to after this.
-								data.shrink_to_fit();
-
-								Some(Event::Paste(data))
-							},
-
-							_ => {
-								printnl!("{:?} {:?}", &data[0..len], String::from_utf8((&data[0..len]).to_vec()));
-								Some(Event::Unknown)
-							}
-						}
-					}
-				} else if len == 1 {
-					if data[0] == 127 {
-						Some(Event::SpecialKey(SpecialKey::Backspace))
-					} else {
-						match data[0] {
-							9 => Some(Event::TextChar('\t')),
-							13 => Some(Event::TextChar('\n')),
-							32..=126 => Some(Event::TextChar(char::from_u32(data[0] as u32).expect("this predefined range to only include valid UTF-8"))),
-							u => Some(Event::Byte(u))
-						}
-					}
-				} else {
-					//TODO
-					if let Some(c) = char::from_u32(u32::from_le_bytes([data[0],data[1],data[2],data[3]])) {
-						Some(Event::TextChar(c))
-					} else {
-						Some(Event::Unknown)
+	///Undoes [Input::enter], giving the terminal back in the state it was found in.
+	fn leave(&mut self) {
+		if self.keyboard_flags_pushed {
+			send!("{}", Action::PopKeyboardFlags.to_ansi());
+		}
+		send!("{}{}", Action::MouseDrags(false).to_ansi(), Action::SgrMouse(false).to_ansi());
+		send!("{}{}{}", Action::BracketPaste(false).to_ansi(), Action::AlternateBuffer(false).to_ansi(), Action::FocusReport(false).to_ansi());
+		self.guard = None;
+	}
+	///Stops the process like Ctrl-Z would outside of raw mode, restoring the terminal first and taking it back once the
+	///process is continued. Whatever was on screen has to be redrawn afterwards.
+	pub fn suspend(&mut self) {
+		self.leave();
+		//Without a job control shell to continue it (an orphaned process group), the kernel doesn't stop the process, and
+		//this returns straight away.
+		unsafe { libc::raise(libc::SIGTSTP) };
+		self.enter();
+	}
+	fn handle_reply(&mut self, reply: Reply) {
+		match reply {
+			Reply::KeyboardFlags(_) if !self.keyboard_flags_pushed => {
+				send!("{}", Action::PushKeyboardFlags(KEYBOARD_FLAGS).to_ansi());
+				self.keyboard_flags_pushed = true;
+			},
+			Reply::KeyboardFlags(_) => {},
+		}
+	}
+	///Waits for the next event, until `deadline` passes or one of the `extra` file descriptors becomes readable.
+	pub fn wait(&mut self, deadline: Option<Instant>, extra: &[RawFd]) -> Wait {
+		loop {
+			match self.parser.next_event() {
+				Some(Event::Reply(reply)) => {
+					self.handle_reply(reply);
+					continue;
+				},
+				Some(event) => return Wait::Event(event),
+				None => {},
+			}
+
+			let tty_fd = self.tty.as_raw_fd();
+			let resize_fd = self.resize.as_ref().map(SignalPipe::fd);
+			let mut fds = vec![tty_fd];
+			fds.extend(resize_fd);
+			fds.extend_from_slice(extra);
+
+			//Partial sequences only get a moment to be completed before they're taken as they are.
+			let now = Instant::now();
+			let escape_deadline = self.parser.is_pending().then(|| now + ESCAPE_TIMEOUT);
+			let timeout = [escape_deadline, deadline].into_iter().flatten().min().map(|d| d.saturating_duration_since(now));
+
+			let ready = wait_readable(&fds, timeout);
+			let ready_fds: Vec<RawFd> = fds.iter().zip(&ready).filter(|(_, r)| **r).map(|(fd, _)| *fd).collect();
+
+			if resize_fd.is_some_and(|fd| ready_fds.contains(&fd)) && self.resize.as_ref().is_some_and(SignalPipe::take) {
+				let (width, height) = self.dimensions();
+				return Wait::Event(Event::Resize(width, height));
+			}
+			let ready_extra: Vec<RawFd> = ready_fds.iter().copied().filter(|fd| extra.contains(fd)).collect();
+			if !ready_extra.is_empty() {
+				return Wait::Ready(ready_extra);
+			}
+
+			if !ready_fds.contains(&tty_fd) {
+				let now = Instant::now();
+				if escape_deadline.is_some_and(|d| d <= now) {
+					if let Some(event) = self.parser.flush() {
+						return Wait::Event(event);
 					}
 				}
-			},
-			Err(e) => match e.kind() {
-				ErrorKind::UnexpectedEof => None,
-				_ => panic!("{e}"),
+				if deadline.is_some_and(|d| d <= now) {
+					return Wait::Timeout;
+				}
+				continue;
+			}
+
+			let mut data = [0; 4096];
+			match self.tty.read(&mut data) {
+				Ok(0) => return Wait::Closed,
+				Ok(len) => self.parser.feed(&data[..len]),
+				Err(e) => match e.kind() {
+					ErrorKind::Interrupted => continue,
+					//Errors like EIO after the terminal hangs up mean no more input is coming, and closing lets the editor
+					//give the terminal back rather than dying in raw mode.
+					_ => return Wait::Closed,
+				}
 			}
 		}
 	}
-
 	pub fn dimensions(&self) -> (u16, u16) {
 		let terminal_utils::TerminalSize { width, height, .. } = terminal_utils::size().unwrap();
 		(width, height)
@@ -155,6 +602,144 @@ impl Input {
 }
 impl Drop for Input {
 	fn drop(&mut self) {
-		send!("{}{}{}", Action::BracketPaste(false).to_ansi(), Action::AlternateBuffer(false).to_ansi(), Action::FocusReport(false).to_ansi());
+		self.leave();
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{key, Event, Key, KeyEvent, KeyKind, Modifiers, MouseButton, MouseEvent, MouseKind, Parser, Reply, SpecialKey};
+
+	fn chord(key: Key, modifiers: Modifiers) -> Event {
+		Event::Key(KeyEvent::new(key, modifiers))
+	}
+
+	fn events(parser: &mut Parser) -> Vec<Event> {
+		std::iter::from_fn(|| parser.next_event()).collect()
+	}
+
+	#[test]
+	fn several_events_per_read() {
+		let mut parser = Parser::default();
+		parser.feed(b"ab\x1b[A\x1b[3~\r\x7f\x08");
+		assert_eq!(events(&mut parser), [
+			key('a'),
+			key('b'),
+			key(SpecialKey::Up),
+			key(SpecialKey::Delete),
+			key('\n'),
+			key(SpecialKey::Backspace),
+			key(SpecialKey::Backspace),
+		]);
+		assert!(!parser.is_pending());
+	}
+
+	#[test]
+	fn split_sequences() {
+		let mut parser = Parser::default();
+		parser.feed(b"\x1b[1");
+		assert_eq!(events(&mut parser), []);
+		parser.feed(b"5~\xe4\xb8");
+		assert_eq!(events(&mut parser), [key(SpecialKey::Fn(5))]);
+		parser.feed(b"\x96");
+		assert_eq!(events(&mut parser), [key('世')]);
+	}
+
+	#[test]
+	fn decode_utf8() {
+		let mut parser = Parser::default();
+		parser.feed("é😀".as_bytes());
+		parser.feed(b"\xff\xc3x");
+		assert_eq!(events(&mut parser), [
+			key('é'),
+			key('😀'),
+			Event::Byte(0xff),
+			Event::Byte(0xc3),
+			key('x'),
+		]);
+	}
+
+	#[test]
+	fn lone_escape() {
+		let mut parser = Parser::default();
+		parser.feed(b"\x1b");
+		assert_eq!(events(&mut parser), []);
+		assert_eq!(parser.flush(), Some(key(SpecialKey::Escape)));
+
+		parser.feed(b"\x1b\x1b[B");
+		assert_eq!(events(&mut parser), [key(SpecialKey::Escape), key(SpecialKey::Down)]);
+	}
+
+	#[test]
+	fn bracketed_paste() {
+		let mut parser = Parser::default();
+		parser.feed(b"\x1b[200~one\x1b[A");
+		assert_eq!(events(&mut parser), []);
+		assert_eq!(parser.flush(), None);
+		parser.feed(b"two\x1b[20");
+		assert_eq!(events(&mut parser), []);
+		parser.feed(b"1~x");
+		assert_eq!(events(&mut parser), [Event::Paste(b"one\x1b[Atwo".to_vec()), key('x')]);
+	}
+
+	#[test]
+	fn modifiers() {
+		let mut parser = Parser::default();
+		parser.feed(b"\x13\x1b[1;5A\x1bx\x1b[3;3~\x1b[Z\x1b[1;10P");
+		assert_eq!(events(&mut parser), [
+			chord(Key::Char('s'), Modifiers::CTRL),
+			chord(Key::Special(SpecialKey::Up), Modifiers::CTRL),
+			chord(Key::Char('x'), Modifiers::ALT),
+			chord(Key::Special(SpecialKey::Delete), Modifiers::ALT),
+			chord(Key::Char('\t'), Modifiers::SHIFT),
+			chord(Key::Special(SpecialKey::Fn(1)), Modifiers::SHIFT | Modifiers::SUPER),
+		]);
+
+		//Alt chords can be split across reads like any other sequence.
+		parser.feed(b"\x1b\xc3");
+		assert_eq!(events(&mut parser), []);
+		parser.feed(b"\xa9");
+		assert_eq!(events(&mut parser), [chord(Key::Char('é'), Modifiers::ALT)]);
+	}
+
+	#[test]
+	fn kitty_keys() {
+		let mut parser = Parser::default();
+		parser.feed(b"\x1b[?1u\x1b[105;5u\x1b[9u\x1b[97;6u\x1b[97:65;2u\x1b[27u\x1b[1;5:3A\x1b[3;1:2~\x1b[57399u");
+		assert_eq!(events(&mut parser), [
+			Event::Reply(Reply::KeyboardFlags(1)),
+			chord(Key::Char('i'), Modifiers::CTRL),
+			key('\t'),
+			chord(Key::Char('A'), Modifiers::CTRL),
+			key('A'),
+			key(SpecialKey::Escape),
+			Event::Key(KeyEvent { kind: KeyKind::Release, ..KeyEvent::new(Key::Special(SpecialKey::Up), Modifiers::CTRL) }),
+			Event::Key(KeyEvent { kind: KeyKind::Repeat, ..SpecialKey::Delete.into() }),
+			Event::Unknown,
+		]);
+	}
+
+	#[test]
+	fn mouse() {
+		let mouse = |kind, modifiers, x, y| Event::Mouse(MouseEvent { kind, modifiers, x, y });
+
+		let mut parser = Parser::default();
+		parser.feed(b"\x1b[<0;5;2M\x1b[<32;6;2M\x1b[<0;6;3m\x1b[<65;1;1M\x1b[<18;10;20M\x1b[<35;3;4M\x1b[<129;1;1M");
+		assert_eq!(events(&mut parser), [
+			mouse(MouseKind::Press(MouseButton::Left), Modifiers::NONE, 4, 1),
+			mouse(MouseKind::Drag(MouseButton::Left), Modifiers::NONE, 5, 1),
+			mouse(MouseKind::Release(MouseButton::Left), Modifiers::NONE, 5, 2),
+			mouse(MouseKind::ScrollDown, Modifiers::NONE, 0, 0),
+			mouse(MouseKind::Press(MouseButton::Right), Modifiers::CTRL, 9, 19),
+			mouse(MouseKind::Move, Modifiers::NONE, 2, 3),
+			mouse(MouseKind::Press(MouseButton::Other(9)), Modifiers::NONE, 0, 0),
+		]);
+	}
+
+	#[test]
+	fn unknown_sequences() {
+		let mut parser = Parser::default();
+		parser.feed(b"\x1b[99zq");
+		assert_eq!(events(&mut parser), [Event::Unknown, key('q')]);
 	}
 }
@@ -1,12 +1,17 @@
 
 pub mod ansi;
+pub mod event_loop;
 pub mod control;
 pub mod style;
 pub mod input;
+pub mod screen;
+pub mod signal;
+pub mod width;
 
 pub mod prelude {
-	pub use super::control::{Action, Motion};
+	pub use super::control::Action;
 	pub use super::style::{Color, Style};
-	pub use super::input::{Event, SpecialKey};
+	pub use super::input::{Event, Key, KeyEvent, KeyKind, Modifiers, MouseButton, MouseEvent, MouseKind, SpecialKey};
+	pub use super::screen::Screen;
 	pub use super::ansi::ToAnsi;
 }
\ No newline at end of file
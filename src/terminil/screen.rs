@@ -0,0 +1,249 @@
+//! A model of the terminal screen as a grid of cells.
+//!
+//! Frames are drawn into a back buffer, and [Screen::render] compares it against the front buffer (what the terminal is
+//! currently showing) so only the cells which changed are sent to the terminal.
+
+use std::io::Write;
+
+use super::{ansi::{output, ToAnsi}, control::{Action, Motion}, style::Style, width};
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cell {
+	///The grapheme cluster shown in the cell. This is empty for the cell covered by the right half of a wide character.
+	content: String,
+	style: Style,
+} impl Cell {
+	fn blank(style: Style) -> Self {
+		Cell { content: " ".to_string(), style }
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct Screen {
+	width: u16,
+	height: u16,
+	front: Vec<Cell>,
+	back: Vec<Cell>,
+	cursor: Option<(u16, u16)>,
+	front_cursor: Option<(u16, u16)>,
+	///Set when the terminal's contents are unknown, so the next frame has to be drawn in full.
+	invalidated: bool,
+} impl Screen {
+	#[cfg(test)]
+	pub fn new(width: u16, height: u16) -> Self {
+		let mut screen = Screen::default();
+		screen.resize(width, height);
+
+		screen
+	}
+	///Returns the `(width, height)` of the screen.
+	pub fn size(&self) -> (u16, u16) {
+		(self.width, self.height)
+	}
+	///Changes the size of the screen. Both buffers are cleared, and the next frame is drawn in full.
+	pub fn resize(&mut self, width: u16, height: u16) {
+		self.width = width;
+		self.height = height;
+		self.back = vec![Cell::blank(Style::default()); width as usize * height as usize];
+		self.front = self.back.clone();
+		self.invalidated = true;
+	}
+	///Makes the next frame be drawn in full, for when something else may have drawn over the terminal.
+	pub fn invalidate(&mut self) {
+		self.invalidated = true;
+	}
+	///Fills the back buffer with blank cells.
+	pub fn clear(&mut self, style: Style) {
+		self.back.fill(Cell::blank(style));
+	}
+	///Sets where the terminal's cursor is shown after the next frame, or hides it.
+	pub fn set_cursor(&mut self, position: Option<(u16, u16)>) {
+		self.cursor = position;
+	}
+
+	fn index(&self, x: u16, y: u16) -> usize {
+		y as usize * self.width as usize + x as usize
+	}
+	fn set(&mut self, x: u16, y: u16, cell: Cell) {
+		let i = self.index(x, y);
+
+		//Overwriting either half of a wide character leaves the other half blank.
+		if x + 1 < self.width && self.back[i + 1].content.is_empty() {
+			self.back[i + 1] = Cell::blank(self.back[i + 1].style);
+		}
+		if !cell.content.is_empty() && self.back[i].content.is_empty() && x > 0 {
+			self.back[i - 1] = Cell::blank(self.back[i - 1].style);
+		}
+
+		self.back[i] = cell;
+	}
+
+	///Draws a grapheme cluster at `(x, y)`, returning the number of cells it covers. Anything off the screen is cut off.
+	pub fn put(&mut self, x: u16, y: u16, cluster: &str, style: Style) -> u16 {
+		if x >= self.width || y >= self.height {
+			return 0;
+		}
+		//Control characters would move the terminal's cursor, so they are drawn as blanks instead.
+		let cluster = if cluster.starts_with(char::is_control) { " " } else { cluster };
+
+		let cells = width::width(cluster) as u16;
+		if x + cells > self.width {
+			//A wide character which doesn't fit can't be drawn at all.
+			self.set(x, y, Cell::blank(style));
+			return 1;
+		}
+
+		self.set(x, y, Cell { content: cluster.to_string(), style });
+		for i in 1..cells {
+			self.set(x + i, y, Cell { content: String::new(), style });
+		}
+
+		cells
+	}
+	///Draws text starting at `(x, y)`, returning the number of cells it covers. Characters of width zero are drawn in
+	///the same cell as the character before them.
+	pub fn print(&mut self, x: u16, y: u16, text: &str, style: Style) -> u16 {
+		let mut col = x;
+		let mut chars = text.char_indices().peekable();
+		while let Some((start, _)) = chars.next() {
+			let mut end = text.len();
+			while let Some(&(i, c)) = chars.peek() {
+				if width::char_width(c) != 0 {
+					end = i;
+					break;
+				}
+				chars.next();
+			}
+			col += self.put(col, y, &text[start..end], style);
+		}
+
+		col - x
+	}
+
+	///Returns the output which updates the terminal from the last frame to the one in the back buffer, which then
+	///becomes the front buffer.
+	pub fn render(&mut self) -> String {
+		let mut out = String::new();
+		if self.invalidated {
+			out += &Action::EraseScreen.to_ansi();
+		}
+
+		//Where the terminal's cursor is and which style it is using, when they are known.
+		let mut position = None;
+		let mut style = None;
+		for y in 0..self.height {
+			for x in 0..self.width {
+				let i = self.index(x, y);
+				let cell = &self.back[i];
+				//The right halves of wide characters are drawn along with their left halves.
+				if cell.content.is_empty() || (!self.invalidated && *cell == self.front[i]) {
+					continue;
+				}
+
+				match position {
+					Some((from, line)) if line == y && from == x => (),
+					//Rewriting a few unchanged cells is shorter than any motion, as long as it doesn't need a new style.
+					Some((from, line)) if line == y && x - from <= 3 && self.can_rewrite(from, x, y, style) => {
+						for gap in from..x {
+							out += &self.back[self.index(gap, y)].content;
+						}
+					},
+					Some((_, line)) if line == y => out += &Motion::LineAbsolute(x).to_ansi(),
+					_ => out += &Motion::ToPosition(x, y).to_ansi(),
+				}
+				if style != Some(cell.style) {
+					out += &cell.style.transition_from(style);
+					style = Some(cell.style);
+				}
+				out += &cell.content;
+
+				let next = x + width::width(&cell.content) as u16;
+				//Terminals defer wrapping after the last column, so the cursor's position there isn't reliable.
+				position = if next < self.width { Some((next, y)) } else { None };
+			}
+		}
+
+		if !out.is_empty() || self.cursor != self.front_cursor {
+			out.insert_str(0, &Action::CursorVisible(false).to_ansi());
+			if let Some((x, y)) = self.cursor {
+				out += &Motion::ToPosition(x, y).to_ansi();
+				out += &Action::CursorVisible(true).to_ansi();
+			}
+		}
+
+		self.front.clone_from(&self.back);
+		self.front_cursor = self.cursor;
+		self.invalidated = false;
+
+		out
+	}
+	///Returns true if the cells on line `y` from `start` to `end` can be written again in the given style.
+	fn can_rewrite(&self, start: u16, end: u16, y: u16, style: Option<Style>) -> bool {
+		(start..end).all(|x| {
+			let cell = &self.back[self.index(x, y)];
+			Some(cell.style) == style && width::width(&cell.content) == 1
+		})
+	}
+	///Sends the next frame to the terminal in a single write.
+	pub fn present(&mut self) {
+		let out = self.render();
+		if !out.is_empty() {
+			let mut terminal = output();
+			let _ = terminal.write_all(out.as_bytes());
+			let _ = terminal.flush();
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::Screen;
+	use crate::terminil::{ansi::strip, style::{Color, Style}};
+
+	#[test]
+	fn only_send_changes() {
+		let mut screen = Screen::new(10, 3);
+		screen.render();
+		screen.print(0, 0, "hello", Style::default());
+		assert_eq!(strip(&screen.render()), "hello");
+
+		screen.print(0, 0, "hello", Style::default());
+		assert_eq!(screen.render(), "");
+
+		screen.print(0, 0, "help", Style::default());
+		let out = screen.render();
+		assert_eq!(strip(&out), "p");
+		assert!(out.contains("\x1b[1;4H"));
+
+		//Small gaps are filled in rather than moved over.
+		screen.print(0, 0, "jelly", Style::default());
+		assert_eq!(strip(&screen.render()), "jelly");
+	}
+
+	#[test]
+	fn switch_styles() {
+		let mut screen = Screen::new(10, 1);
+		screen.render();
+
+		screen.print(0, 0, "ab", Style::fg(Color::Red));
+		screen.print(2, 0, "c", Style::fg(Color::Red).inherit(Style::BOLD));
+		let out = screen.render();
+		assert_eq!(out.matches("\x1b[31m").count(), 1);
+		assert!(out.contains("\x1b[1mc"));
+	}
+
+	#[test]
+	fn wide_characters() {
+		let mut screen = Screen::new(4, 1);
+		assert_eq!(screen.print(0, 0, "界e\u{301}", Style::default()), 3);
+		assert_eq!(strip(&screen.render()), "界e\u{301} ");
+
+		//Overwriting half of a wide character blanks the other half.
+		screen.print(1, 0, "x", Style::default());
+		assert_eq!(strip(&screen.render()), " x");
+
+		//Wide characters which would cross the edge aren't drawn.
+		assert_eq!(screen.print(3, 0, "界", Style::default()), 1);
+		assert_eq!(strip(&screen.render()), "");
+	}
+}
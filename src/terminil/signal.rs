@@ -0,0 +1,69 @@
+//! Turns signals into readable file descriptors (the self-pipe trick), so they can be waited on alongside input.
+
+use std::{io::Error, os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd}, sync::atomic::{AtomicI32, Ordering}};
+
+use libc::c_int;
+
+///The write end of the pipe for each signal number, or -1 for signals which aren't being forwarded.
+static PIPES: [AtomicI32; 65] = [const { AtomicI32::new(-1) }; 65];
+
+extern "C" fn forward(signal: c_int) {
+	//The interrupted code could be about to check errno, so it has to survive the write.
+	let errno = unsafe { *libc::__errno_location() };
+	let fd = PIPES[signal as usize].load(Ordering::Relaxed);
+	if fd >= 0 {
+		let byte = 0u8;
+		unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+	}
+	unsafe { *libc::__errno_location() = errno };
+}
+
+///A pipe which becomes readable whenever a signal arrives. The signal's previous handler is restored when this is dropped.
+pub struct SignalPipe {
+	signal: c_int,
+	read: OwnedFd,
+	_write: OwnedFd,
+	previous: libc::sigaction,
+} impl SignalPipe {
+	pub fn new(signal: c_int) -> Result<Self, Error> {
+		let mut fds = [0; 2];
+		if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } != 0 {
+			return Err(Error::last_os_error());
+		}
+		let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+		PIPES[signal as usize].store(write.as_raw_fd(), Ordering::Relaxed);
+
+		let mut previous: libc::sigaction = unsafe { std::mem::zeroed() };
+		let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+		action.sa_sigaction = forward as *const () as usize;
+		//Restarting interrupted calls keeps blocking reads elsewhere from failing with EINTR.
+		action.sa_flags = libc::SA_RESTART;
+		unsafe { libc::sigemptyset(&mut action.sa_mask) };
+		if unsafe { libc::sigaction(signal, &action, &mut previous) } != 0 {
+			PIPES[signal as usize].store(-1, Ordering::Relaxed);
+			return Err(Error::last_os_error());
+		}
+
+		Ok(SignalPipe { signal, read, _write: write, previous })
+	}
+	///Returns the file descriptor which becomes readable when the signal arrives.
+	pub fn fd(&self) -> RawFd {
+		self.read.as_raw_fd()
+	}
+	///Empties the pipe, returning true if the signal arrived since the last call.
+	pub fn take(&self) -> bool {
+		let mut arrived = false;
+		let mut data = [0u8; 64];
+		while unsafe { libc::read(self.fd(), data.as_mut_ptr() as *mut libc::c_void, data.len()) } > 0 {
+			arrived = true;
+		}
+
+		arrived
+	}
+}
+impl Drop for SignalPipe {
+	fn drop(&mut self) {
+		unsafe { libc::sigaction(self.signal, &self.previous, std::ptr::null_mut()) };
+		PIPES[self.signal as usize].store(-1, Ordering::Relaxed);
+	}
+}
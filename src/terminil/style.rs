@@ -1,8 +1,7 @@
 use super::ansi::{consts::*, ToAnsi};
 use const_format::formatcp;
 
-#[allow(unused)]
-
+#[allow(unused, clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum Color {
@@ -111,7 +110,7 @@ pub enum Color {
 		if let Color::Uncolored = self {
 			format!("{CSI}39m")
 		} else if let Color::Unset = self {
-			format!("")
+			String::new()
 		} else {
 			format!("{CSI}{}m", self.as_fg_codes())
 		}
@@ -121,14 +120,14 @@ pub enum Color {
 		if let Color::Uncolored = self {
 			format!("{CSI}49m")
 		} else if let Color::Unset = self {
-			format!("")
+			String::new()
 		} else {
 			format!("{CSI}{}m", self.as_bg_codes())
 		}
 	}
 }
 
-#[derive(Default, Debug, Clone, Copy, serde::Deserialize)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, serde::Deserialize)]
 pub struct Style {
 	fg: Option<Color>,
 	bg: Option<Color>,
@@ -184,14 +183,36 @@ pub struct Style {
 	pub fn stylize(&self, data: &str) -> String {
 		self.to_ansi() + data
 	}
+
+	///Returns the sequence which switches the terminal from the `previous` style to this one, only changing the
+	///attributes which differ. Unset colors mean the terminal's default colors here. Without a previous style, every
+	///attribute is set.
+	pub fn transition_from(&self, previous: Option<Style>) -> String {
+		let mut out = String::new();
+
+		let bold = self.bold.unwrap_or_default();
+		if previous.is_none_or(|p| p.bold.unwrap_or_default() != bold) {
+			out += if bold {formatcp!("{CSI}1m")} else {formatcp!("{CSI}22m")};
+		}
+		let fg = self.fg.unwrap_or(Color::Uncolored);
+		if previous.is_none_or(|p| p.fg.unwrap_or(Color::Uncolored) != fg) {
+			out += &fg.as_fg();
+		}
+		let bg = self.bg.unwrap_or(Color::Uncolored);
+		if previous.is_none_or(|p| p.bg.unwrap_or(Color::Uncolored) != bg) {
+			out += &bg.as_bg();
+		}
+
+		out
+	}
 }
 
 impl ToAnsi for Style {
 	fn to_ansi(&self) -> String {
-		return format!("{}{}{}",
+		format!("{}{}{}",
 			if self.bold.unwrap_or_default() {formatcp!("{CSI}1m")} else {formatcp!("{CSI}22m")},
 			self.fg.map_or(String::default(), |v| v.as_fg()),
 			self.bg.map_or(String::default(), |v| v.as_bg()),
-		);
+		)
 	}
 }
\ No newline at end of file
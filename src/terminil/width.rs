@@ -0,0 +1,163 @@
+//! Approximate display widths of characters, following the East Asian Width property closely enough for common text.
+
+///Characters which take up two cells.
+const WIDE: &[(u32, u32)] = &[
+	(0x1100, 0x115F),
+	(0x231A, 0x231B),
+	(0x2329, 0x232A),
+	(0x23E9, 0x23EC),
+	(0x23F0, 0x23F0),
+	(0x23F3, 0x23F3),
+	(0x25FD, 0x25FE),
+	(0x2614, 0x2615),
+	(0x2648, 0x2653),
+	(0x267F, 0x267F),
+	(0x2693, 0x2693),
+	(0x26A1, 0x26A1),
+	(0x26AA, 0x26AB),
+	(0x26BD, 0x26BE),
+	(0x26C4, 0x26C5),
+	(0x26CE, 0x26CE),
+	(0x26D4, 0x26D4),
+	(0x26EA, 0x26EA),
+	(0x26F2, 0x26F3),
+	(0x26F5, 0x26F5),
+	(0x26FA, 0x26FA),
+	(0x26FD, 0x26FD),
+	(0x2705, 0x2705),
+	(0x270A, 0x270B),
+	(0x2728, 0x2728),
+	(0x274C, 0x274C),
+	(0x274E, 0x274E),
+	(0x2753, 0x2755),
+	(0x2757, 0x2757),
+	(0x2795, 0x2797),
+	(0x27B0, 0x27B0),
+	(0x27BF, 0x27BF),
+	(0x2B1B, 0x2B1C),
+	(0x2B50, 0x2B50),
+	(0x2B55, 0x2B55),
+	(0x2E80, 0x303E),
+	(0x3041, 0x33FF),
+	(0x3400, 0x4DBF),
+	(0x4E00, 0x9FFF),
+	(0xA000, 0xA4CF),
+	(0xA960, 0xA97F),
+	(0xAC00, 0xD7A3),
+	(0xF900, 0xFAFF),
+	(0xFE10, 0xFE19),
+	(0xFE30, 0xFE6F),
+	(0xFF00, 0xFF60),
+	(0xFFE0, 0xFFE6),
+	(0x16FE0, 0x16FE4),
+	(0x17000, 0x18CFF),
+	(0x1B000, 0x1B2FF),
+	(0x1F004, 0x1F004),
+	(0x1F0CF, 0x1F0CF),
+	(0x1F18E, 0x1F18E),
+	(0x1F191, 0x1F19A),
+	(0x1F1E6, 0x1F1FF),
+	(0x1F200, 0x1F251),
+	(0x1F300, 0x1F320),
+	(0x1F32D, 0x1F335),
+	(0x1F337, 0x1F37C),
+	(0x1F37E, 0x1F393),
+	(0x1F3A0, 0x1F3CA),
+	(0x1F3CF, 0x1F3D3),
+	(0x1F3E0, 0x1F3F0),
+	(0x1F3F4, 0x1F3F4),
+	(0x1F3F8, 0x1F43E),
+	(0x1F440, 0x1F440),
+	(0x1F442, 0x1F4FC),
+	(0x1F4FF, 0x1F53D),
+	(0x1F54B, 0x1F54E),
+	(0x1F550, 0x1F567),
+	(0x1F57A, 0x1F57A),
+	(0x1F595, 0x1F596),
+	(0x1F5A4, 0x1F5A4),
+	(0x1F5FB, 0x1F64F),
+	(0x1F680, 0x1F6C5),
+	(0x1F6CC, 0x1F6CC),
+	(0x1F6D0, 0x1F6D2),
+	(0x1F6D5, 0x1F6D7),
+	(0x1F6DC, 0x1F6DF),
+	(0x1F6EB, 0x1F6EC),
+	(0x1F6F4, 0x1F6FC),
+	(0x1F7E0, 0x1F7EB),
+	(0x1F7F0, 0x1F7F0),
+	(0x1F90C, 0x1F93A),
+	(0x1F93C, 0x1F945),
+	(0x1F947, 0x1F9FF),
+	(0x1FA70, 0x1FAFF),
+	(0x20000, 0x2FFFD),
+	(0x30000, 0x3FFFD),
+];
+
+///Characters which don't take up any cells of their own, and are drawn over the previous character.
+const ZERO_WIDTH: &[(u32, u32)] = &[
+	(0x0300, 0x036F),
+	(0x0483, 0x0489),
+	(0x0591, 0x05BD),
+	(0x0610, 0x061A),
+	(0x064B, 0x065F),
+	(0x0E31, 0x0E31),
+	(0x0E34, 0x0E3A),
+	(0x0E47, 0x0E4E),
+	(0x1160, 0x11FF),
+	(0x1AB0, 0x1AFF),
+	(0x1DC0, 0x1DFF),
+	(0x200B, 0x200F),
+	(0x2028, 0x202E),
+	(0x2060, 0x2064),
+	(0x20D0, 0x20FF),
+	(0xFE00, 0xFE0F),
+	(0xFE20, 0xFE2F),
+	(0xFEFF, 0xFEFF),
+	(0x1F3FB, 0x1F3FF),
+	(0xE0000, 0xE0FFF),
+];
+
+fn in_table(table: &[(u32, u32)], c: char) -> bool {
+	let c = c as u32;
+	table.binary_search_by(|&(start, end)| {
+		if end < c {
+			std::cmp::Ordering::Less
+		} else if start > c {
+			std::cmp::Ordering::Greater
+		} else {
+			std::cmp::Ordering::Equal
+		}
+	}).is_ok()
+}
+
+///Returns the number of cells `c` takes up on its own.
+pub fn char_width(c: char) -> usize {
+	if in_table(ZERO_WIDTH, c) {
+		0
+	} else if in_table(WIDE, c) {
+		2
+	} else {
+		1
+	}
+}
+
+///Returns the number of cells a grapheme cluster takes up. Every cluster takes up at least one cell, even if it is
+///made of characters which would otherwise be drawn over another.
+pub fn width(cluster: &str) -> usize {
+	let mut chars = cluster.chars();
+	let Some(first) = chars.next() else {
+		return 0;
+	};
+
+	//Variation selectors switch symbols between their text and emoji presentations.
+	if chars.any(|c| c == '\u{FE0F}') {
+		2
+	} else {
+		char_width(first).max(1)
+	}
+}
+
+///Returns the number of cells a string takes up, treating characters of width zero as part of the one before them.
+pub fn str_width(text: &str) -> usize {
+	text.chars().map(char_width).sum()
+}